@@ -0,0 +1,112 @@
+//! Serial and parallel generation and rendering side by side.
+//!
+//! Run with `cargo bench --features parallel --bench parallel`.
+
+use cave_terrarin_generator::{
+    parallel,
+    render::{render_triangles, terrain_triangles, Viewport},
+    terrain::{index_field, sample_noise, threshold_field, Terrain},
+    window::Screen,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const SIZES: [usize; 3] = [256, 1024, 4096];
+const THRESHOLD: f64 = 0.33;
+
+fn bench_sample_noise(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample_noise");
+    group.sample_size(10);
+
+    for size in SIZES {
+        group.bench_with_input(BenchmarkId::new("serial", size), &size, |b, &size| {
+            b.iter(|| sample_noise(size, size, 0, 0, &[]))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", size), &size, |b, &size| {
+            b.iter(|| parallel::sample_noise(size, size, 0, 0, &[]))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_threshold_field(c: &mut Criterion) {
+    let mut group = c.benchmark_group("threshold_field");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let scalar_field = sample_noise(size, size, 0, 0, &[]);
+
+        group.bench_with_input(
+            BenchmarkId::new("serial", size),
+            &scalar_field,
+            |b, field| b.iter(|| threshold_field(field, THRESHOLD)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("parallel", size),
+            &scalar_field,
+            |b, field| b.iter(|| parallel::threshold_field(field, THRESHOLD)),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_index_field(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index_field");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let thresholded_field = threshold_field(&sample_noise(size, size, 0, 0, &[]), THRESHOLD);
+
+        group.bench_with_input(
+            BenchmarkId::new("serial", size),
+            &thresholded_field,
+            |b, field| b.iter(|| index_field(field)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("parallel", size),
+            &thresholded_field,
+            |b, field| b.iter(|| parallel::index_field(field)),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_render_triangles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_triangles");
+    group.sample_size(10);
+
+    let color = [71, 45, 35, 255];
+
+    for size in [64, 256] {
+        let terrain = Terrain::new(size, size);
+        let mut serial_screen = Screen::headless(2000, 2000);
+        let mut parallel_screen = Screen::headless(2000, 2000);
+        let triangles = terrain_triangles(&terrain, &Viewport::fit(&serial_screen, &terrain));
+
+        group.bench_with_input(
+            BenchmarkId::new("serial", size),
+            &triangles,
+            |b, triangles| b.iter(|| render_triangles(&mut serial_screen, triangles, &color)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("parallel", size),
+            &triangles,
+            |b, triangles| {
+                b.iter(|| parallel::render_triangles(&mut parallel_screen, triangles, &color))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_sample_noise,
+    bench_threshold_field,
+    bench_index_field,
+    bench_render_triangles
+);
+criterion_main!(benches);
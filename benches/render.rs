@@ -0,0 +1,62 @@
+use cave_terrarin_generator::{
+    color::WHITE,
+    render::{render_string, render_terrain, render_triangle},
+    terrain::Terrain,
+    window::Screen,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const SCREEN_SIZE: usize = 1000;
+const TERRAIN_COLOR: [u8; 4] = [71, 45, 35, 255];
+
+fn bench_render_terrain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_terrain");
+    let mut screen = Screen::headless(SCREEN_SIZE, SCREEN_SIZE);
+
+    for size in [40, 128, 512] {
+        let terrain = Terrain::new(size, size);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &terrain, |b, terrain| {
+            b.iter(|| render_terrain(&mut screen, terrain, &TERRAIN_COLOR))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_render_triangle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_triangle");
+    let mut screen = Screen::headless(SCREEN_SIZE, SCREEN_SIZE);
+
+    // Half of a square, the largest triangle a marching squares cell produces
+    for size in [25, 100, 500] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| render_triangle(&mut screen, 0, 0, 0, size, size, size, &TERRAIN_COLOR))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_render_string(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_string");
+    let mut screen = Screen::headless(SCREEN_SIZE, SCREEN_SIZE);
+
+    let instruction_str = "a add mode\ns subtract mode\ng toggle grid\n";
+
+    for scale in [1, 2, 4] {
+        group.bench_with_input(BenchmarkId::from_parameter(scale), &scale, |b, &scale| {
+            b.iter(|| render_string(&mut screen, instruction_str, 10, 10, scale, &WHITE))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_render_terrain,
+    bench_render_triangle,
+    bench_render_string
+);
+criterion_main!(benches);
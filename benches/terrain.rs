@@ -0,0 +1,44 @@
+use cave_terrarin_generator::terrain::Terrain;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+fn bench_new(c: &mut Criterion) {
+    let mut group = c.benchmark_group("terrain_new");
+    group.sample_size(10);
+
+    for size in [40, 128, 512, 1024] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| Terrain::new(size, size))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_modify_scalar_field(c: &mut Criterion) {
+    let mut group = c.benchmark_group("modify_scalar_field");
+
+    for size in [40, 128, 512] {
+        // A diagonal stroke across the world, one sample per step like a mouse drag
+        let stroke: Vec<(usize, usize)> = (0..size).map(|i| (i, i)).collect();
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter_batched_ref(
+                || Terrain::new(size, size),
+                |terrain| {
+                    for &(row, col) in &stroke {
+                        terrain.modify_scalar_field(row, col, 1.0);
+                    }
+                    for &(row, col) in &stroke {
+                        terrain.modify_scalar_field(row, col, 0.0);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_new, bench_modify_scalar_field);
+criterion_main!(benches);
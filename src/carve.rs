@@ -0,0 +1,177 @@
+use log::debug;
+
+use crate::terrain::Terrain;
+
+/// Rectangle of marching squares cells, bounds inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellRect {
+    pub row_start: usize,
+    pub col_start: usize,
+    pub row_end: usize,
+    pub col_end: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Carve {
+    /// Cells with a corner on a sample that lost rock, `None` when nothing was carved.
+    /// The contour can move inside a cell without its marching squares case changing,
+    /// so all of them need redrawing.
+    pub changed_cells: Option<CellRect>,
+    /// Scalar value taken out of the field, summed over samples. Each sample covers one
+    /// square world unit, so this is the volume of rock weighted by its density.
+    pub material_removed: f64,
+}
+
+impl Terrain {
+    /// Blast a hole into the rock, as for an explosion.
+    ///
+    /// `center` and `radius` are in world units. Samples lose up to `strength` from their
+    /// scalar value, the most at the center fading out to nothing at the radius, so weak
+    /// blasts only chip away at the surface. Only the cells around the blast are rebuilt.
+    pub fn carve_circle(&mut self, center: (f64, f64), radius: f64, strength: f64) -> Carve {
+        let mut carve = Carve {
+            changed_cells: None,
+            material_removed: 0.0,
+        };
+
+        if radius <= 0.0 || strength <= 0.0 {
+            return carve;
+        }
+
+        // Samples whose center could lie within the radius, end exclusive
+        let col_start = (center.0 - radius - 0.5).floor().max(0.0) as usize;
+        let row_start = (center.1 - radius - 0.5).floor().max(0.0) as usize;
+        let col_end = ((center.0 + radius + 0.5).ceil().max(0.0) as usize).min(self.width);
+        let row_end = ((center.1 + radius + 0.5).ceil().max(0.0) as usize).min(self.height);
+
+        if col_start >= col_end || row_start >= row_end {
+            return carve;
+        }
+
+        // Samples that lost rock, bounds inclusive
+        let mut carved_samples: Option<CellRect> = None;
+        for row in row_start..row_end {
+            for col in col_start..col_end {
+                let (x, y) = self.sample_to_world(row, col);
+                let distance = ((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt();
+                if distance >= radius {
+                    continue;
+                }
+
+                let falloff = 1.0 - (distance / radius).powi(2);
+                let scalar = &mut self.scalar_field[row][col];
+                let carved = (*scalar - strength * falloff).max(0.0);
+                if carved == *scalar {
+                    continue;
+                }
+
+                carve.material_removed += *scalar - carved;
+                *scalar = carved;
+
+                carved_samples = Some(match carved_samples {
+                    None => CellRect {
+                        row_start: row,
+                        col_start: col,
+                        row_end: row,
+                        col_end: col,
+                    },
+                    Some(rect) => CellRect {
+                        row_start: rect.row_start.min(row),
+                        col_start: rect.col_start.min(col),
+                        row_end: rect.row_end.max(row),
+                        col_end: rect.col_end.max(col),
+                    },
+                });
+            }
+        }
+
+        if let Some(samples) = carved_samples {
+            // Every cell with a corner on a carved sample
+            let cells = CellRect {
+                row_start: samples.row_start.saturating_sub(1),
+                col_start: samples.col_start.saturating_sub(1),
+                row_end: samples.row_end.min(self.height - 2),
+                col_end: samples.col_end.min(self.width - 2),
+            };
+
+            self.update_index_grid(
+                cells.row_start,
+                cells.col_start,
+                cells.row_end,
+                cells.col_end,
+            );
+            carve.changed_cells = Some(cells);
+        }
+
+        debug!("carve: {:?}", carve);
+        carve
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_terrain() -> Terrain {
+        Terrain::from_scalar_field(vec![vec![1.0; 10]; 10])
+    }
+
+    #[test]
+    fn carve_reports_every_touched_cell() {
+        let mut terrain = solid_terrain();
+
+        // The four samples around (5, 5) lose half their rock, which leaves them solid
+        // and every marching squares case as it was
+        let carve = terrain.carve_circle((5.0, 5.0), 1.0, 1.0);
+
+        assert!((carve.material_removed - 2.0).abs() < 1e-9);
+        assert_eq!(
+            carve.changed_cells,
+            Some(CellRect {
+                row_start: 3,
+                col_start: 3,
+                row_end: 5,
+                col_end: 5,
+            })
+        );
+        assert!(terrain
+            .index_grid
+            .iter()
+            .flatten()
+            .all(|&index| index == 15));
+    }
+
+    #[test]
+    fn carve_is_clipped_to_the_terrain() {
+        let mut terrain = solid_terrain();
+
+        let carve = terrain.carve_circle((0.0, 0.0), 2.0, 1.0);
+
+        let removed: f64 = terrain
+            .scalar_field
+            .iter()
+            .flatten()
+            .map(|scalar| 1.0 - scalar)
+            .sum();
+        assert!((carve.material_removed - removed).abs() < 1e-9);
+        assert_eq!(
+            carve.changed_cells,
+            Some(CellRect {
+                row_start: 0,
+                col_start: 0,
+                row_end: 1,
+                col_end: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn carving_empty_cave_changes_nothing() {
+        let mut terrain = Terrain::from_scalar_field(vec![vec![0.0; 10]; 10]);
+
+        let carve = terrain.carve_circle((5.0, 5.0), 3.0, 1.0);
+
+        assert_eq!(carve.material_removed, 0.0);
+        assert_eq!(carve.changed_cells, None);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::BTreeSet;
+
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    sdf::{point_distance, smooth_min, Shape},
+    terrain::Terrain,
+};
+
+/// Attempts at placing a chamber before settling for fewer of them
+const PLACEMENT_TRIES: usize = 1000;
+
+/// How the Voronoi cells are turned into chambers and passages
+#[derive(Clone, Copy, Debug)]
+pub struct ChamberLayout {
+    /// Number of chambers, fewer fit when `min_spacing` is too large
+    pub chambers: usize,
+    /// Smallest distance in world units between the centers of two chambers
+    pub min_spacing: f64,
+    /// Rock left on either side of the border between two Voronoi cells, and along
+    /// the edges of the terrain
+    pub wall_thickness: f64,
+    /// Width of the throats joining neighbouring chambers
+    pub passage_width: f64,
+    /// Chance between 0 and 1 of keeping a passage that is not needed to connect every
+    /// chamber, which adds loops to the cave
+    pub loop_chance: f64,
+    /// Distance in world units over which passages blend into the chambers
+    pub softness: f64,
+}
+
+/// Around seven chambers for a 40 x 40 terrain with a few loops
+pub fn cave_chambers() -> ChamberLayout {
+    ChamberLayout {
+        chambers: 7,
+        min_spacing: 10.0,
+        wall_thickness: 2.5,
+        passage_width: 2.0,
+        loop_chance: 0.3,
+        softness: 1.5,
+    }
+}
+
+/// Chamber centers in world space and the pairs of chambers joined by a passage
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChamberGraph {
+    pub centers: Vec<(f64, f64)>,
+    pub passages: Vec<(usize, usize)>,
+}
+
+impl Terrain {
+    /// Replace the scalar field with chambers joined by narrow passages.
+    ///
+    /// Points picked by `seed` become the centers of Voronoi cells, each cell is hollowed
+    /// out into a chamber that keeps `wall_thickness` from its border. Cells sharing a
+    /// border are neighbours, and passages are cut between them along a minimum spanning
+    /// tree so every chamber can be reached.
+    pub fn generate_chambers(&mut self, layout: &ChamberLayout, seed: u64) -> ChamberGraph {
+        assert!(
+            (0.0..=1.0).contains(&layout.loop_chance),
+            "loop_chance must be between 0 and 1, got {}",
+            layout.loop_chance
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let (min_x, min_y) = self.sample_to_world(0, 0);
+        let (max_x, max_y) = self.sample_to_world(self.height - 1, self.width - 1);
+
+        // Keep the centers clear of the edges so no passage breaks through them
+        let margin_x = (layout.min_spacing / 2.0).min((max_x - min_x) / 2.0);
+        let margin_y = (layout.min_spacing / 2.0).min((max_y - min_y) / 2.0);
+
+        let mut centers: Vec<(f64, f64)> = Vec::with_capacity(layout.chambers);
+        for _ in 0..PLACEMENT_TRIES {
+            if centers.len() == layout.chambers {
+                break;
+            }
+
+            let point = (
+                rng.gen_range(min_x + margin_x..=max_x - margin_x),
+                rng.gen_range(min_y + margin_y..=max_y - margin_y),
+            );
+            if centers
+                .iter()
+                .all(|&center| point_distance(center, point) >= layout.min_spacing)
+            {
+                centers.push(point);
+            }
+        }
+        debug!("placed {} chambers", centers.len());
+
+        // Nearest and second nearest center of every sample, the Worley F1 and F2
+        let worley: Vec<Vec<(usize, f64, f64)>> = (0..self.height)
+            .map(|row| {
+                (0..self.width)
+                    .map(|col| nearest_centers(&centers, self.sample_to_world(row, col)))
+                    .collect()
+            })
+            .collect();
+
+        let passages = select_passages(&centers, &worley, layout.loop_chance, &mut rng);
+        debug!("cutting {} passages", passages.len());
+
+        let throats: Vec<Shape> = passages
+            .iter()
+            .map(|&(a, b)| Shape::Capsule {
+                start: centers[a],
+                end: centers[b],
+                radius: layout.passage_width / 2.0,
+            })
+            .collect();
+
+        for (row, cells) in worley.iter().enumerate() {
+            for (col, &(_, nearest, second)) in cells.iter().enumerate() {
+                let (x, y) = self.sample_to_world(row, col);
+
+                // Half the gap between F1 and F2 is the distance to the cell border
+                let border = ((second - nearest) / 2.0)
+                    .min(x - min_x)
+                    .min(max_x - x)
+                    .min(y - min_y)
+                    .min(max_y - y);
+                let chamber = layout.wall_thickness - border;
+
+                let distance = throats
+                    .iter()
+                    .map(|throat| throat.distance(x, y))
+                    .fold(chamber, |a, b| smooth_min(a, b, layout.softness));
+
+                self.scalar_field[row][col] = self.distance_to_scalar(-distance);
+            }
+        }
+
+        self.construct_index_grid();
+
+        ChamberGraph { centers, passages }
+    }
+}
+
+/// Index of the nearest center and the distances to the nearest and second nearest
+fn nearest_centers(centers: &[(f64, f64)], point: (f64, f64)) -> (usize, f64, f64) {
+    let mut nearest = (usize::MAX, f64::INFINITY, f64::INFINITY);
+
+    for (index, &center) in centers.iter().enumerate() {
+        let distance = point_distance(center, point);
+        if distance < nearest.1 {
+            nearest = (index, distance, nearest.1);
+        } else if distance < nearest.2 {
+            nearest.2 = distance;
+        }
+    }
+
+    nearest
+}
+
+/// Passages along a minimum spanning tree of the neighbouring cells, plus the
+/// remaining neighbours with a chance of `loop_chance` each
+fn select_passages(
+    centers: &[(f64, f64)],
+    worley: &[Vec<(usize, f64, f64)>],
+    loop_chance: f64,
+    rng: &mut StdRng,
+) -> Vec<(usize, usize)> {
+    // Cells are neighbours when two adjacent samples belong to them
+    let mut pairs = BTreeSet::new();
+    for (row, cells) in worley.iter().enumerate() {
+        for (col, &(cell, _, _)) in cells.iter().enumerate() {
+            let right = cells.get(col + 1).map(|&(cell, _, _)| cell);
+            let below = worley.get(row + 1).map(|cells| cells[col].0);
+
+            for other in [right, below].into_iter().flatten() {
+                if other != cell {
+                    pairs.insert((cell.min(other), cell.max(other)));
+                }
+            }
+        }
+    }
+    let mut neighbours: Vec<(usize, usize)> = pairs.into_iter().collect();
+    neighbours.sort_by(|&(a_0, b_0), &(a_1, b_1)| {
+        point_distance(centers[a_0], centers[b_0])
+            .total_cmp(&point_distance(centers[a_1], centers[b_1]))
+    });
+
+    // Kruskal's algorithm, shortest passages first
+    let mut parents: Vec<usize> = (0..centers.len()).collect();
+    let mut passages = Vec::new();
+    for (a, b) in neighbours {
+        let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+
+        if root_a != root_b {
+            parents[root_a] = root_b;
+            passages.push((a, b));
+        } else if rng.gen_bool(loop_chance) {
+            passages.push((a, b));
+        }
+    }
+
+    passages
+}
+
+fn find_root(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(loop_chance: f64) -> ChamberLayout {
+        ChamberLayout {
+            loop_chance,
+            ..cave_chambers()
+        }
+    }
+
+    /// Whether `passages` join all `chambers` into one graph
+    fn connects_all(chambers: usize, passages: &[(usize, usize)]) -> bool {
+        let mut parents: Vec<usize> = (0..chambers).collect();
+        for &(a, b) in passages {
+            let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+            parents[root_a] = root_b;
+        }
+
+        let root = find_root(&mut parents, 0);
+        (0..chambers).all(|chamber| find_root(&mut parents, chamber) == root)
+    }
+
+    #[test]
+    fn passages_are_a_spanning_tree_without_loops() {
+        for seed in 0..8 {
+            let graph = Terrain::new(40, 40).generate_chambers(&layout(0.0), seed);
+
+            assert!(graph.centers.len() > 1);
+            assert_eq!(graph.passages.len(), graph.centers.len() - 1);
+            assert!(connects_all(graph.centers.len(), &graph.passages));
+        }
+    }
+
+    #[test]
+    fn loops_only_add_passages_between_neighbours() {
+        for seed in 0..8 {
+            // Every passage between neighbouring cells is kept when loops always are
+            let tree = Terrain::new(40, 40).generate_chambers(&layout(0.0), seed);
+            let some = Terrain::new(40, 40).generate_chambers(&layout(0.5), seed);
+            let all = Terrain::new(40, 40).generate_chambers(&layout(1.0), seed);
+
+            assert_eq!(tree.centers, all.centers);
+            assert_eq!(some.centers, all.centers);
+            for passage in &tree.passages {
+                assert!(some.passages.contains(passage));
+            }
+            for passage in &some.passages {
+                assert!(all.passages.contains(passage));
+            }
+        }
+    }
+
+    #[test]
+    fn walls_keep_their_thickness_at_the_edges() {
+        let layout = cave_chambers();
+
+        for seed in 0..8 {
+            let mut terrain = Terrain::new(40, 40);
+            terrain.generate_chambers(&layout, seed);
+
+            for row in 0..terrain.height {
+                for col in 0..terrain.width {
+                    let edge = row
+                        .min(col)
+                        .min(terrain.height - 1 - row)
+                        .min(terrain.width - 1 - col);
+                    if (edge as f64) < layout.wall_thickness {
+                        assert!(
+                            terrain.scalar_field[row][col] > terrain.threshold(),
+                            "sample ({}, {}) with seed {}",
+                            row,
+                            col,
+                            seed
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "loop_chance")]
+    fn loop_chance_above_one_is_rejected() {
+        Terrain::new(40, 40).generate_chambers(&layout(1.5), 0);
+    }
+}
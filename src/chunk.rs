@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::{modifier::NoiseModifier, terrain::Terrain};
+
+/// `(chunk_row, chunk_col)` of a chunk in the world
+pub type ChunkCoord = (i64, i64);
+
+/// Terrain split into fixed-size chunks that are generated around a camera on demand.
+///
+/// Each chunk covers `chunk_size` x `chunk_size` marching squares cells, so it holds
+/// `chunk_size + 1` samples per side and shares its last row and column of samples
+/// with its neighbours. Shared samples are always written to every chunk that holds
+/// them, keeping the contours continuous across seams.
+pub struct ChunkedTerrain {
+    pub chunk_size: usize,
+    pub load_radius: usize,
+    pub evict_radius: usize,
+    /// Applied to the noise of every chunk generated from now on, see `NoiseModifier`
+    pub modifiers: Vec<NoiseModifier>,
+    chunks: HashMap<ChunkCoord, Terrain>,
+    // Edits are kept per chunk in local sample coordinates so they survive eviction
+    edits: HashMap<ChunkCoord, HashMap<(usize, usize), f64>>,
+}
+
+impl ChunkedTerrain {
+    pub fn new(chunk_size: usize, load_radius: usize, evict_radius: usize) -> Self {
+        debug_assert!(chunk_size > 0);
+        debug_assert!(evict_radius >= load_radius);
+
+        Self {
+            chunk_size,
+            load_radius,
+            evict_radius,
+            modifiers: Vec::new(),
+            chunks: HashMap::new(),
+            edits: HashMap::new(),
+        }
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&ChunkCoord, &Terrain)> {
+        self.chunks.iter()
+    }
+
+    /// Chunk that owns the world sample at `(row, col)`
+    pub fn chunk_of(&self, row: i64, col: i64) -> ChunkCoord {
+        let size = self.chunk_size as i64;
+        (row.div_euclid(size), col.div_euclid(size))
+    }
+
+    /// World position of the first sample of a chunk
+    pub fn chunk_origin(&self, coord: ChunkCoord) -> (i64, i64) {
+        let size = self.chunk_size as i64;
+        (coord.0 * size, coord.1 * size)
+    }
+
+    /// Load chunks around the camera and evict the ones that drifted too far away.
+    ///
+    /// The camera position is given in world sample coordinates.
+    pub fn stream(&mut self, camera_row: f64, camera_col: f64) {
+        let center = self.chunk_of(camera_row.floor() as i64, camera_col.floor() as i64);
+
+        let evict_radius = self.evict_radius as i64;
+        self.chunks.retain(|coord, _| {
+            let distance = (coord.0 - center.0).abs().max((coord.1 - center.1).abs());
+            if distance > evict_radius {
+                debug!("evicting chunk {:?}", coord);
+            }
+            distance <= evict_radius
+        });
+
+        let load_radius = self.load_radius as i64;
+        for chunk_row in center.0 - load_radius..=center.0 + load_radius {
+            for chunk_col in center.1 - load_radius..=center.1 + load_radius {
+                let coord = (chunk_row, chunk_col);
+                if !self.chunks.contains_key(&coord) {
+                    let chunk = self.generate_chunk(coord);
+                    self.chunks.insert(coord, chunk);
+                }
+            }
+        }
+    }
+
+    fn generate_chunk(&self, coord: ChunkCoord) -> Terrain {
+        debug!("generating chunk {:?}", coord);
+
+        let (origin_row, origin_col) = self.chunk_origin(coord);
+        let samples = self.chunk_size + 1;
+        let mut chunk =
+            Terrain::with_modifiers(samples, samples, origin_row, origin_col, &self.modifiers);
+
+        if let Some(edits) = self.edits.get(&coord) {
+            for (&(row, col), &scalar) in edits {
+                chunk.scalar_field[row][col] = scalar;
+            }
+            chunk.construct_index_grid();
+        }
+
+        chunk
+    }
+
+    /// Set the world sample at `(row, col)` in every chunk that holds it, loaded or not.
+    pub fn modify_scalar_field(&mut self, row: i64, col: i64, new_scalar: f64) {
+        debug_assert!((0.0..=1.0).contains(&new_scalar));
+
+        let size = self.chunk_size as i64;
+        let owner = self.chunk_of(row, col);
+
+        // Samples on the first row or column of a chunk are also the last row or column
+        // of the chunk above or to the left of it
+        let mut chunk_rows = vec![owner.0];
+        if row.rem_euclid(size) == 0 {
+            chunk_rows.push(owner.0 - 1);
+        }
+        let mut chunk_cols = vec![owner.1];
+        if col.rem_euclid(size) == 0 {
+            chunk_cols.push(owner.1 - 1);
+        }
+
+        for &chunk_row in &chunk_rows {
+            for &chunk_col in &chunk_cols {
+                let coord = (chunk_row, chunk_col);
+                let (origin_row, origin_col) = self.chunk_origin(coord);
+                let local = ((row - origin_row) as usize, (col - origin_col) as usize);
+
+                self.edits
+                    .entry(coord)
+                    .or_default()
+                    .insert(local, new_scalar);
+
+                if let Some(chunk) = self.chunks.get_mut(&coord) {
+                    chunk.modify_scalar_field(local.0, local.1, new_scalar);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    const SIZE: usize = 16;
+
+    /// World loaded around the origin, chunks -1 to 1 in both directions
+    fn loaded_world() -> ChunkedTerrain {
+        let mut world = ChunkedTerrain::new(SIZE, 1, 2);
+        world.stream(0.0, 0.0);
+        world
+    }
+
+    #[test]
+    fn seams_share_samples_and_contours() {
+        let world = loaded_world();
+        let (left, right) = (&world.chunks[&(0, 0)], &world.chunks[&(0, 1)]);
+
+        for row in 0..=SIZE {
+            assert_eq!(left.scalar_field[row][SIZE], right.scalar_field[row][0]);
+        }
+
+        // Contour ends on the seam, which runs through the centers of the shared samples
+        let seam_x = (SIZE as f64 + 0.5) * 100.0;
+        let seam_ends = |chunk: &Terrain, origin_col: f64| -> HashSet<(i64, i64)> {
+            chunk
+                .contour_segments()
+                .iter()
+                .flat_map(|&[x_0, y_0, x_1, y_1]| [(x_0, y_0), (x_1, y_1)])
+                .map(|(x, y)| (((x + origin_col) * 100.0).round(), (y * 100.0).round()))
+                .filter(|&(x, _)| x == seam_x)
+                .map(|(x, y)| (x as i64, y as i64))
+                .collect()
+        };
+
+        let left_ends = seam_ends(left, 0.0);
+        assert!(!left_ends.is_empty());
+        assert_eq!(left_ends, seam_ends(right, SIZE as f64));
+    }
+
+    #[test]
+    fn edits_on_seams_reach_every_chunk() {
+        let mut world = loaded_world();
+
+        // On the seam between chunks (0, 0) and (0, 1)
+        world.modify_scalar_field(5, SIZE as i64, 1.0);
+        assert_eq!(world.chunks[&(0, 0)].scalar_field[5][SIZE], 1.0);
+        assert_eq!(world.chunks[&(0, 1)].scalar_field[5][0], 1.0);
+
+        // On the corner shared by four chunks
+        world.modify_scalar_field(0, 0, 0.0);
+        for (coord, local) in [
+            ((0, 0), (0, 0)),
+            ((-1, 0), (SIZE, 0)),
+            ((0, -1), (0, SIZE)),
+            ((-1, -1), (SIZE, SIZE)),
+        ] {
+            assert_eq!(world.chunks[&coord].scalar_field[local.0][local.1], 0.0);
+        }
+    }
+
+    #[test]
+    fn edits_survive_eviction() {
+        let mut world = loaded_world();
+        world.modify_scalar_field(3, SIZE as i64, 1.0);
+        let index_grid = world.chunks[&(0, 0)].index_grid.clone();
+
+        // Far enough that both chunks holding the sample are evicted
+        let far = (SIZE * 10) as f64;
+        world.stream(0.0, far);
+        assert!(!world.chunks.contains_key(&(0, 0)));
+        assert!(!world.chunks.contains_key(&(0, 1)));
+
+        world.stream(0.0, 0.0);
+        assert_eq!(world.chunks[&(0, 0)].scalar_field[3][SIZE], 1.0);
+        assert_eq!(world.chunks[&(0, 1)].scalar_field[3][0], 1.0);
+        assert_eq!(world.chunks[&(0, 0)].index_grid, index_grid);
+    }
+}
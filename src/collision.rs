@@ -0,0 +1,387 @@
+use crate::terrain::Terrain;
+
+/// Where a ray first meets the rock
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub point: (f64, f64),
+    /// Unit normal of the contour at the hit, pointing out of the rock into the cave
+    pub normal: (f64, f64),
+    /// World units travelled from the origin of the ray
+    pub distance: f64,
+}
+
+impl Terrain {
+    /// Cast a ray from `origin` along `direction` against the marching squares contour,
+    /// with its ends interpolated between the samples like `interpolated_cell_segments`.
+    ///
+    /// Only the cells the ray passes through are tested, in the order it passes through
+    /// them. Rays starting inside rock hit straight away with a normal facing back along
+    /// the ray. Returns `None` when nothing is hit within `max_distance` world units.
+    pub fn raycast(
+        &self,
+        origin: (f64, f64),
+        direction: (f64, f64),
+        max_distance: f64,
+    ) -> Option<RayHit> {
+        let length = (direction.0 * direction.0 + direction.1 * direction.1).sqrt();
+        if length == 0.0 || max_distance < 0.0 || self.width < 2 || self.height < 2 {
+            return None;
+        }
+        let direction = (direction.0 / length, direction.1 / length);
+
+        if self.is_solid(origin.0, origin.1) {
+            return Some(RayHit {
+                point: origin,
+                normal: (-direction.0, -direction.1),
+                distance: 0.0,
+            });
+        }
+
+        // Walk the cell grid, which starts at the first sample center
+        let (grid_x, grid_y) = (origin.0 - 0.5, origin.1 - 0.5);
+        let cols = (self.width - 1) as f64;
+        let rows = (self.height - 1) as f64;
+
+        let (t_enter, t_exit) =
+            clip_to_box((grid_x, grid_y), direction, (cols, rows), max_distance)?;
+
+        let enter_x = grid_x + direction.0 * t_enter;
+        let enter_y = grid_y + direction.1 * t_enter;
+        let mut col = (enter_x.floor().max(0.0) as usize).min(self.width - 2);
+        let mut row = (enter_y.floor().max(0.0) as usize).min(self.height - 2);
+
+        // Distance along the ray to the next vertical and horizontal cell border
+        let (col_step, mut t_next_col, t_col_delta) = axis_step(grid_x, direction.0, col);
+        let (row_step, mut t_next_row, t_row_delta) = axis_step(grid_y, direction.1, row);
+
+        loop {
+            let hit = self
+                .interpolated_cell_segments(row, col)
+                .filter_map(|segment| ray_segment_hit(origin, direction, &segment))
+                .filter(|hit| hit.distance <= max_distance)
+                .min_by(|a, b| a.distance.total_cmp(&b.distance));
+            if hit.is_some() {
+                return hit;
+            }
+
+            if t_next_col < t_next_row {
+                if t_next_col > t_exit {
+                    return None;
+                }
+                t_next_col += t_col_delta;
+                col = col.checked_add_signed(col_step)?;
+            } else {
+                if t_next_row > t_exit {
+                    return None;
+                }
+                t_next_row += t_row_delta;
+                row = row.checked_add_signed(row_step)?;
+            }
+
+            if col > self.width - 2 || row > self.height - 2 {
+                return None;
+            }
+        }
+    }
+
+    /// Whether a circle in world space touches the rock, tested against the same
+    /// interpolated contour as `raycast`
+    pub fn overlaps_circle(&self, center: (f64, f64), radius: f64) -> bool {
+        if self.is_solid(center.0, center.1) {
+            return true;
+        }
+        if self.width < 2 || self.height < 2 {
+            return false;
+        }
+
+        // With the center in the cave, the circle reaches rock only by crossing the contour
+        let col_start = (center.0 - radius - 0.5).floor().max(0.0) as usize;
+        let row_start = (center.1 - radius - 0.5).floor().max(0.0) as usize;
+        let col_end = ((center.0 + radius - 0.5).floor().max(0.0) as usize).min(self.width - 2);
+        let row_end = ((center.1 + radius - 0.5).floor().max(0.0) as usize).min(self.height - 2);
+
+        for row in row_start..=row_end {
+            for col in col_start..=col_end {
+                if self
+                    .interpolated_cell_segments(row, col)
+                    .any(|segment| segment_distance(center, &segment) <= radius)
+                {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+/// Range of distances along the ray inside the box from `(0, 0)` to `size`, cut off at `max_distance`
+fn clip_to_box(
+    origin: (f64, f64),
+    direction: (f64, f64),
+    size: (f64, f64),
+    max_distance: f64,
+) -> Option<(f64, f64)> {
+    let mut t_enter: f64 = 0.0;
+    let mut t_exit = max_distance;
+
+    for (start, step, end) in [
+        (origin.0, direction.0, size.0),
+        (origin.1, direction.1, size.1),
+    ] {
+        if step == 0.0 {
+            if start < 0.0 || start > end {
+                return None;
+            }
+            continue;
+        }
+
+        let t_0 = -start / step;
+        let t_1 = (end - start) / step;
+        t_enter = t_enter.max(t_0.min(t_1));
+        t_exit = t_exit.min(t_0.max(t_1));
+    }
+
+    (t_enter <= t_exit).then_some((t_enter, t_exit))
+}
+
+/// Step direction, distance along the ray to the first border and distance between
+/// borders when walking cells along one axis
+fn axis_step(start: f64, direction: f64, cell: usize) -> (isize, f64, f64) {
+    if direction > 0.0 {
+        (1, (cell as f64 + 1.0 - start) / direction, 1.0 / direction)
+    } else if direction < 0.0 {
+        (-1, (cell as f64 - start) / direction, -1.0 / direction)
+    } else {
+        (0, f64::INFINITY, f64::INFINITY)
+    }
+}
+
+/// Intersection of a ray with a contour segment, `direction` being a unit vector
+fn ray_segment_hit(
+    origin: (f64, f64),
+    direction: (f64, f64),
+    segment: &[f64; 4],
+) -> Option<RayHit> {
+    let [x_0, y_0, x_1, y_1] = *segment;
+    let (edge_x, edge_y) = (x_1 - x_0, y_1 - y_0);
+
+    let denominator = cross(direction, (edge_x, edge_y));
+    if denominator.abs() < f64::EPSILON {
+        return None;
+    }
+
+    let to_start = (x_0 - origin.0, y_0 - origin.1);
+    let distance = cross(to_start, (edge_x, edge_y)) / denominator;
+    let along_segment = cross(to_start, direction) / denominator;
+    if distance < 0.0 || !(0.0..=1.0).contains(&along_segment) {
+        return None;
+    }
+
+    // Segments keep the rock on their right, so the left hand side faces the cave
+    let edge_length = (edge_x * edge_x + edge_y * edge_y).sqrt();
+
+    Some(RayHit {
+        point: (
+            origin.0 + direction.0 * distance,
+            origin.1 + direction.1 * distance,
+        ),
+        normal: (edge_y / edge_length, -edge_x / edge_length),
+        distance,
+    })
+}
+
+fn cross(a: (f64, f64), b: (f64, f64)) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn segment_distance(point: (f64, f64), segment: &[f64; 4]) -> f64 {
+    let [x_0, y_0, x_1, y_1] = *segment;
+    let (dx, dy) = (x_1 - x_0, y_1 - y_0);
+
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((point.0 - x_0) * dx + (point.1 - y_0) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    ((point.0 - x_0 - t * dx).powi(2) + (point.1 - y_0 - t * dy).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    /// 20x20 terrain of open cave with the given samples set to solid rock. The rock is
+    /// twice the threshold, so the interpolated contour runs through the edge midpoints.
+    fn terrain_with_rock(is_rock: impl Fn(usize, usize) -> bool) -> Terrain {
+        let mut terrain = Terrain::new(20, 20);
+        let rock = 2.0 * terrain.threshold();
+        for row in 0..terrain.height {
+            for col in 0..terrain.width {
+                terrain.scalar_field[row][col] = if is_rock(row, col) { rock } else { 0.0 };
+            }
+        }
+        terrain.construct_index_grid();
+
+        terrain
+    }
+
+    /// Rock filling every sample from column 10 onwards, the contour runs along x = 10
+    fn wall() -> Terrain {
+        terrain_with_rock(|_, col| col >= 10)
+    }
+
+    /// Square block of rock over samples 10 to 14, its sides run along 10 and 15
+    fn block() -> Terrain {
+        terrain_with_rock(|row, col| (10..=14).contains(&row) && (10..=14).contains(&col))
+    }
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < EPSILON && (actual.1 - expected.1).abs() < EPSILON,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn raycast_hits_wall_head_on() {
+        let hit = wall().raycast((2.0, 5.2), (1.0, 0.0), 100.0).unwrap();
+
+        assert_close(hit.point, (10.0, 5.2));
+        assert_close(hit.normal, (-1.0, 0.0));
+        assert!((hit.distance - 8.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn raycast_hits_wall_diagonally() {
+        let hit = wall().raycast((2.0, 2.2), (3.0, 3.0), 100.0).unwrap();
+
+        assert_close(hit.point, (10.0, 10.2));
+        assert_close(hit.normal, (-1.0, 0.0));
+        assert!((hit.distance - 8.0 * std::f64::consts::SQRT_2).abs() < EPSILON);
+    }
+
+    #[test]
+    fn raycast_hits_block_from_every_side() {
+        let terrain = block();
+
+        let cases = [
+            ((3.0, 12.5), (1.0, 0.0), (10.0, 12.5), (-1.0, 0.0)),
+            ((18.0, 12.5), (-1.0, 0.0), (15.0, 12.5), (1.0, 0.0)),
+            ((12.5, 2.0), (0.0, 1.0), (12.5, 10.0), (0.0, -1.0)),
+            ((12.5, 19.0), (0.0, -1.0), (12.5, 15.0), (0.0, 1.0)),
+        ];
+        for (origin, direction, point, normal) in cases {
+            let hit = terrain.raycast(origin, direction, 100.0).unwrap();
+
+            assert_close(hit.point, point);
+            assert_close(hit.normal, normal);
+        }
+    }
+
+    #[test]
+    fn raycast_hits_interpolated_contour() {
+        // Solid rock from column 10 on, so the threshold of 0.33 is crossed a third of
+        // the way from the cave sample at 9.5 to the rock at 10.5
+        let mut terrain = wall();
+        for scalars in &mut terrain.scalar_field {
+            scalars[10..].fill(1.0);
+        }
+        // A shallower slope into the rock further down
+        for scalars in &mut terrain.scalar_field[10..] {
+            scalars[10] = 0.5;
+        }
+        terrain.construct_index_grid();
+
+        let hit = terrain.raycast((2.0, 5.0), (1.0, 0.0), 100.0).unwrap();
+        assert_close(hit.point, (9.83, 5.0));
+        assert_close(hit.normal, (-1.0, 0.0));
+        assert!((hit.distance - 7.83).abs() < EPSILON);
+
+        let hit = terrain.raycast((2.0, 15.0), (1.0, 0.0), 100.0).unwrap();
+        assert_close(hit.point, (9.5 + 0.33 / 0.5, 15.0));
+        assert_close(hit.normal, (-1.0, 0.0));
+
+        // Circles that would reach the edge midpoints at x = 10 the other way round
+        assert!(terrain.overlaps_circle((8.7, 5.0), 1.2));
+        assert!(!terrain.overlaps_circle((8.9, 15.0), 1.2));
+    }
+
+    #[test]
+    fn points_between_the_contours_agree_on_the_rock() {
+        // The interpolated wall is at 9.83, in front of the edge midpoints at x = 10
+        let mut terrain = wall();
+        for scalars in &mut terrain.scalar_field {
+            scalars[10..].fill(1.0);
+        }
+        terrain.construct_index_grid();
+
+        assert!(terrain.is_solid(9.9, 5.0));
+        assert!(terrain.overlaps_circle((9.9, 5.0), 0.01));
+        let hit = terrain.raycast((9.9, 5.0), (1.0, 0.0), 100.0).unwrap();
+        assert_eq!(hit.distance, 0.0);
+
+        assert!(!terrain.is_solid(9.8, 5.0));
+        assert!(!terrain.overlaps_circle((9.8, 5.0), 0.01));
+        let hit = terrain.raycast((9.8, 5.0), (1.0, 0.0), 100.0).unwrap();
+        assert_close(hit.point, (9.83, 5.0));
+    }
+
+    #[test]
+    fn raycast_misses() {
+        let terrain = block();
+
+        // Pointing away, passing beside and falling short of the block
+        assert_eq!(terrain.raycast((3.0, 12.5), (-1.0, 0.0), 100.0), None);
+        assert_eq!(terrain.raycast((3.0, 5.0), (1.0, 0.0), 100.0), None);
+        assert_eq!(terrain.raycast((3.0, 12.5), (1.0, 0.0), 6.5), None);
+        // Starting off the terrain and never crossing it
+        assert_eq!(terrain.raycast((-5.0, -5.0), (-1.0, 0.0), 100.0), None);
+    }
+
+    #[test]
+    fn raycast_enters_terrain_from_outside() {
+        let hit = block().raycast((-20.0, 12.5), (1.0, 0.0), 100.0).unwrap();
+
+        assert_close(hit.point, (10.0, 12.5));
+        assert!((hit.distance - 30.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn raycast_from_inside_rock_hits_immediately() {
+        let hit = block().raycast((12.5, 12.5), (1.0, 0.0), 100.0).unwrap();
+
+        assert_close(hit.point, (12.5, 12.5));
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn point_inside_solid() {
+        let terrain = block();
+
+        assert!(terrain.is_solid(12.5, 12.5));
+        assert!(terrain.is_solid(10.2, 12.5));
+        assert!(!terrain.is_solid(9.8, 12.5));
+        assert!(!terrain.is_solid(5.0, 5.0));
+        assert!(!terrain.is_solid(-1.0, 12.5));
+    }
+
+    #[test]
+    fn circle_overlap() {
+        let terrain = block();
+
+        // Approaching the left side, which runs along x = 10
+        assert!(!terrain.overlaps_circle((8.0, 12.5), 1.5));
+        assert!(terrain.overlaps_circle((8.0, 12.5), 2.5));
+        // Fully inside the rock
+        assert!(terrain.overlaps_circle((12.5, 12.5), 0.5));
+        // Far from anything
+        assert!(!terrain.overlaps_circle((3.0, 3.0), 2.0));
+    }
+}
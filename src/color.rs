@@ -1,7 +1,30 @@
-type Color = [u8; 4];
+pub type Color = [u8; 4];
 
 pub const BLACK: Color = [0, 0, 0, 255];
 pub const WHITE: Color = [255, 255, 255, 255];
 pub const RED: Color = [255, 0, 0, 255];
 pub const GREEN: Color = [0, 255, 0, 255];
 pub const BLUE: Color = [0, 0, 255, 255];
+
+/// A color for the `index`th item of a set that stays distinct from its neighbours in the set
+pub fn distinct_color(index: usize) -> Color {
+    // Step the hue by the golden ratio so consecutive colors land far apart on the wheel
+    let hue = (index as f64 * 0.618_033_988_749_895).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+
+    let (r, g, b) = match hue as usize {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+
+    [
+        (r * 255.0).round() as u8,
+        (g * 255.0).round() as u8,
+        (b * 255.0).round() as u8,
+        255,
+    ]
+}
@@ -0,0 +1,254 @@
+use log::debug;
+use noise::{NoiseFn, Perlin};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{surface::SurfaceKind, terrain::Terrain};
+
+/// How and where one kind of prop grows
+#[derive(Clone, Debug)]
+pub struct DecorationRule {
+    pub name: String,
+    /// Class of surface the prop is attached to
+    pub surface: SurfaceKind,
+    /// Smallest distance in world units to any other prop
+    pub spacing: f64,
+    /// Open cave in world units needed in front of the surface, along its normal
+    pub min_clearance: f64,
+    /// Fraction of the surface offered a prop, 0 for none and 1 for everywhere the spacing
+    /// allows. Noise decides which parts are offered one, so props grow in patches.
+    pub density: f64,
+    /// Frequency of the density noise per world unit
+    pub noise_scale: f64,
+    /// Length of the prop in world units, used for drawing
+    pub size: f64,
+}
+
+/// A prop placed on the contour
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decoration {
+    /// Index of the rule that placed it
+    pub rule: usize,
+    /// Attachment point on the contour in world space
+    pub position: (f64, f64),
+    /// Unit normal of the surface, the direction the prop grows in
+    pub normal: (f64, f64),
+}
+
+/// Stalactites hanging from ceilings, stalagmites on floors and crystals on walls
+pub fn cave_decorations() -> Vec<DecorationRule> {
+    vec![
+        DecorationRule {
+            name: "stalactite".to_string(),
+            surface: SurfaceKind::Ceiling,
+            spacing: 2.5,
+            min_clearance: 4.0,
+            density: 0.6,
+            noise_scale: 0.15,
+            size: 1.5,
+        },
+        DecorationRule {
+            name: "stalagmite".to_string(),
+            surface: SurfaceKind::Floor,
+            spacing: 3.0,
+            min_clearance: 3.0,
+            density: 0.5,
+            noise_scale: 0.15,
+            size: 1.2,
+        },
+        DecorationRule {
+            name: "crystal".to_string(),
+            surface: SurfaceKind::Wall,
+            spacing: 4.0,
+            min_clearance: 1.5,
+            density: 0.45,
+            noise_scale: 0.1,
+            size: 0.8,
+        },
+    ]
+}
+
+impl Terrain {
+    /// Place props on the contour following `rules`, the same seed always gives the
+    /// same placement.
+    ///
+    /// Candidates along every surface are visited in a random order and kept when they
+    /// are further than the spacing from every prop kept so far, which spreads the props
+    /// out like Poisson-disc samples. Earlier rules are placed first and win where rules
+    /// compete for space.
+    pub fn place_decorations(
+        &self,
+        rules: &[DecorationRule],
+        max_floor_angle: f64,
+        max_ceiling_angle: f64,
+        seed: u64,
+    ) -> Vec<Decoration> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let surfaces: Vec<_> = self.surfaces(max_floor_angle, max_ceiling_angle).collect();
+
+        let mut decorations: Vec<Decoration> = Vec::new();
+        for (rule_index, rule) in rules.iter().enumerate() {
+            let perlin = Perlin::new(rng.gen());
+
+            // One candidate at a random point along every matching segment, paired with the
+            // noise at that point
+            let mut candidates: Vec<(f64, Decoration)> = surfaces
+                .iter()
+                .filter(|surface| surface.kind == rule.surface)
+                .map(|surface| {
+                    let [x_0, y_0, x_1, y_1] = surface.segment;
+                    let t: f64 = rng.gen();
+                    let (x, y) = (x_0 + (x_1 - x_0) * t, y_0 + (y_1 - y_0) * t);
+
+                    let noise = perlin.get([x * rule.noise_scale, y * rule.noise_scale]);
+                    let decoration = Decoration {
+                        rule: rule_index,
+                        position: (x, y),
+                        normal: surface.normal,
+                    };
+                    (noise, decoration)
+                })
+                .collect();
+
+            // Keep the density fraction of candidates where the noise is highest. Ranking
+            // rather than thresholding the noise keeps the coverage at the density whatever
+            // the spread of the noise values.
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let offered = (candidates.len() as f64 * rule.density.clamp(0.0, 1.0)).round();
+            candidates.truncate(offered as usize);
+            candidates.shuffle(&mut rng);
+
+            let mut placed = 0;
+            for (_, candidate) in candidates {
+                let (x, y) = candidate.position;
+
+                let spaced = decorations.iter().all(|decoration| {
+                    let spacing = rule.spacing.max(rules[decoration.rule].spacing);
+                    let (dx, dy) = (decoration.position.0 - x, decoration.position.1 - y);
+                    dx * dx + dy * dy >= spacing * spacing
+                });
+                if !spaced || !self.has_clearance(&candidate, rule.min_clearance) {
+                    continue;
+                }
+
+                decorations.push(candidate);
+                placed += 1;
+            }
+            debug!("placed {} {}s", placed, rule.name);
+        }
+
+        decorations
+    }
+
+    /// Whether the cave stays open for `clearance` world units in front of a prop
+    fn has_clearance(&self, decoration: &Decoration, clearance: f64) -> bool {
+        // Start just off the surface so the ray does not hit the segment it starts on
+        const OFFSET: f64 = 1e-3;
+        let (x, y) = decoration.position;
+        let (normal_x, normal_y) = decoration.normal;
+
+        self.raycast(
+            (x + normal_x * OFFSET, y + normal_y * OFFSET),
+            decoration.normal,
+            clearance - OFFSET,
+        )
+        .is_none()
+    }
+}
+
+/// Placement list as CSV with a header, one prop per line as name, position and normal
+pub fn export_decorations(decorations: &[Decoration], rules: &[DecorationRule]) -> String {
+    let mut csv = String::from("name,x,y,normal_x,normal_y\n");
+
+    for decoration in decorations {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            rules[decoration.rule].name,
+            decoration.position.0,
+            decoration.position.1,
+            decoration.normal.0,
+            decoration.normal.1
+        ));
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_4;
+
+    use super::*;
+
+    /// Rock around an open room with a flat floor and ceiling
+    fn room() -> Terrain {
+        let mut scalar_field = vec![vec![1.0; 40]; 20];
+        for row in scalar_field.iter_mut().take(15).skip(5) {
+            for scalar in row.iter_mut().take(37).skip(3) {
+                *scalar = 0.0;
+            }
+        }
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    fn floor_rule(spacing: f64, density: f64) -> DecorationRule {
+        DecorationRule {
+            name: "stalagmite".to_string(),
+            surface: SurfaceKind::Floor,
+            spacing,
+            min_clearance: 1.0,
+            density,
+            noise_scale: 0.15,
+            size: 1.0,
+        }
+    }
+
+    #[test]
+    fn placement_depends_only_on_the_seed() {
+        let terrain = room();
+        let rules = cave_decorations();
+
+        let first = terrain.place_decorations(&rules, FRAC_PI_4, FRAC_PI_4, 7);
+        let second = terrain.place_decorations(&rules, FRAC_PI_4, FRAC_PI_4, 7);
+
+        assert!(!first.is_empty());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn props_keep_their_spacing() {
+        let terrain = room();
+        let rules = cave_decorations();
+
+        let decorations = terrain.place_decorations(&rules, FRAC_PI_4, FRAC_PI_4, 3);
+
+        for (i, a) in decorations.iter().enumerate() {
+            for b in &decorations[i + 1..] {
+                let spacing = rules[a.rule].spacing.max(rules[b.rule].spacing);
+                let (dx, dy) = (a.position.0 - b.position.0, a.position.1 - b.position.1);
+                assert!((dx * dx + dy * dy).sqrt() >= spacing);
+            }
+        }
+    }
+
+    #[test]
+    fn density_is_the_fraction_of_floor_covered() {
+        let terrain = room();
+        let floors = terrain
+            .surfaces(FRAC_PI_4, FRAC_PI_4)
+            .filter(|surface| surface.kind == SurfaceKind::Floor)
+            .count();
+
+        for density in [0.0, 0.1, 0.5, 0.9, 1.0] {
+            let decorations =
+                terrain.place_decorations(&[floor_rule(0.0, density)], FRAC_PI_4, FRAC_PI_4, 5);
+
+            assert_eq!(
+                decorations.len(),
+                (floors as f64 * density).round() as usize
+            );
+            assert!(decorations
+                .iter()
+                .all(|decoration| decoration.normal.1 < 0.0));
+        }
+    }
+}
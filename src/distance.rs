@@ -0,0 +1,232 @@
+use log::debug;
+
+use crate::terrain::{ScalarField, Terrain};
+
+/// Signed Euclidean distance from points of the terrain to the marching squares contour.
+///
+/// Points are laid out like `scalar_field`, from the first sample center to the last,
+/// with `resolution` points per sample spacing, so a resolution of 1 has one value per
+/// sample. Distances are in world units, negative inside rock and positive in the cave.
+pub struct DistanceField {
+    pub resolution: usize,
+    pub width: usize,
+    pub height: usize,
+    pub values: ScalarField,
+}
+
+impl DistanceField {
+    pub fn new(terrain: &Terrain, resolution: usize) -> Self {
+        debug_assert!(resolution > 0);
+
+        let width = (terrain.width - 1) * resolution + 1;
+        let height = (terrain.height - 1) * resolution + 1;
+        let segments = SegmentGrid::new(terrain);
+
+        let mut values = vec![vec![0.0; width]; height];
+        for (row, distances) in values.iter_mut().enumerate() {
+            for (col, distance) in distances.iter_mut().enumerate() {
+                let x = 0.5 + col as f64 / resolution as f64;
+                let y = 0.5 + row as f64 / resolution as f64;
+
+                let unsigned = segments.distance(x, y);
+                *distance = if terrain.is_solid(x, y) {
+                    -unsigned
+                } else {
+                    unsigned
+                };
+            }
+        }
+        debug!(
+            "distance field: {}x{} at resolution {}",
+            width, height, resolution
+        );
+
+        Self {
+            resolution,
+            width,
+            height,
+            values,
+        }
+    }
+
+    /// World position `(x, y)` of a point of the field
+    pub fn point_to_world(&self, row: usize, col: usize) -> (f64, f64) {
+        (
+            0.5 + col as f64 / self.resolution as f64,
+            0.5 + row as f64 / self.resolution as f64,
+        )
+    }
+}
+
+/// Distance in world units from every sample to the nearest wall, 0 inside rock
+pub fn clearance_map(terrain: &Terrain) -> ScalarField {
+    DistanceField::new(terrain, 1)
+        .values
+        .into_iter()
+        .map(|row| row.into_iter().map(|distance| distance.max(0.0)).collect())
+        .collect()
+}
+
+/// Contour segments bucketed by the marching squares cell they were traced in
+struct SegmentGrid {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<Vec<[f64; 4]>>>,
+    /// Number of segments in all cells
+    len: usize,
+}
+
+impl SegmentGrid {
+    fn new(terrain: &Terrain) -> Self {
+        let width = terrain.width - 1;
+        let height = terrain.height - 1;
+
+        let mut cells = vec![vec![Vec::new(); width]; height];
+        let segments = terrain.contour_segments();
+        let len = segments.len();
+        for segment in segments {
+            // Segments never leave their cell, bucket them by their midpoint
+            let mid_x = (segment[0] + segment[2]) / 2.0 - 0.5;
+            let mid_y = (segment[1] + segment[3]) / 2.0 - 0.5;
+            let col = (mid_x.floor() as usize).min(width - 1);
+            let row = (mid_y.floor() as usize).min(height - 1);
+
+            cells[row][col].push(segment);
+        }
+
+        Self {
+            width,
+            height,
+            cells,
+            len,
+        }
+    }
+
+    /// Exact distance to the nearest segment, searching rings of cells outwards from
+    /// the point until no unvisited cell can hold anything closer
+    fn distance(&self, x: f64, y: f64) -> f64 {
+        // Without a wall there is nothing to search for
+        if self.len == 0 {
+            return f64::INFINITY;
+        }
+
+        let col = ((x - 0.5).floor().max(0.0) as usize).min(self.width - 1);
+        let row = ((y - 0.5).floor().max(0.0) as usize).min(self.height - 1);
+
+        let mut best = f64::INFINITY;
+        for ring in 0..self.width.max(self.height) {
+            for (ring_row, ring_col) in self.ring(row, col, ring) {
+                for segment in &self.cells[ring_row][ring_col] {
+                    best = best.min(segment_distance(x, y, segment));
+                }
+            }
+
+            // Every cell past this ring is at least `ring` cells away from the point
+            if best <= ring as f64 {
+                break;
+            }
+        }
+
+        best
+    }
+
+    /// Cells at Chebyshev distance `ring` from `(row, col)` that are on the grid
+    fn ring(&self, row: usize, col: usize, ring: usize) -> Vec<(usize, usize)> {
+        let (row, col, ring) = (row as isize, col as isize, ring as isize);
+        let mut cells = Vec::new();
+
+        for ring_row in row - ring..=row + ring {
+            for ring_col in col - ring..=col + ring {
+                let on_ring = (ring_row - row).abs() == ring || (ring_col - col).abs() == ring;
+                let on_grid = ring_row >= 0
+                    && ring_col >= 0
+                    && (ring_row as usize) < self.height
+                    && (ring_col as usize) < self.width;
+
+                if on_ring && on_grid {
+                    cells.push((ring_row as usize, ring_col as usize));
+                }
+            }
+        }
+
+        cells
+    }
+}
+
+fn segment_distance(x: f64, y: f64, segment: &[f64; 4]) -> f64 {
+    let [x_0, y_0, x_1, y_1] = *segment;
+    let (dx, dy) = (x_1 - x_0, y_1 - y_0);
+
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((x - x_0) * dx + (y - y_0) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let (closest_x, closest_y) = (x_0 + t * dx, y_0 + t * dy);
+    ((x - closest_x).powi(2) + (y - closest_y).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Open cave with a block of rock in rows 4 to 8 and columns 5 to 11
+    fn block_terrain() -> Terrain {
+        let mut scalar_field = vec![vec![0.1; 16]; 12];
+        for row in scalar_field.iter_mut().take(9).skip(4) {
+            for scalar in row.iter_mut().take(12).skip(5) {
+                *scalar = 0.9;
+            }
+        }
+
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    #[test]
+    fn distances_match_brute_force() {
+        let terrain = block_terrain();
+        let segments = terrain.contour_segments();
+        let distance_field = DistanceField::new(&terrain, 3);
+
+        for row in 0..distance_field.height {
+            for col in 0..distance_field.width {
+                let (x, y) = distance_field.point_to_world(row, col);
+                let expected = segments
+                    .iter()
+                    .map(|segment| segment_distance(x, y, segment))
+                    .fold(f64::INFINITY, f64::min);
+
+                let distance = distance_field.values[row][col];
+                assert!((distance.abs() - expected).abs() < 1e-9, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn distances_are_negative_inside_rock() {
+        let terrain = block_terrain();
+        let distance_field = DistanceField::new(&terrain, 1);
+
+        // Sample (6, 8) is in the middle of the block, sample (1, 1) is in the cave
+        assert!(distance_field.values[6][8] < 0.0);
+        assert!(distance_field.values[1][1] > 0.0);
+
+        // The left wall runs halfway between the sample centers at x = 4.5 and 5.5
+        assert_eq!(distance_field.values[6][2], 2.5);
+        assert_eq!(distance_field.values[6][5], -0.5);
+    }
+
+    #[test]
+    fn distance_without_walls_is_infinite() {
+        let terrain = Terrain::from_scalar_field(vec![vec![0.0; 6]; 6]);
+        let distance_field = DistanceField::new(&terrain, 1);
+
+        assert!(distance_field
+            .values
+            .iter()
+            .flatten()
+            .all(|&distance| distance == f64::INFINITY));
+    }
+}
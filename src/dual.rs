@@ -0,0 +1,359 @@
+use crate::terrain::Terrain;
+
+/// Regularization pulling each vertex towards the average of its crossings, keeps the
+/// solve stable when all the normals in a cell are parallel
+const MASS_POINT_WEIGHT: f64 = 0.05;
+
+/// Where the contour crosses a grid edge and the surface normal there
+struct Crossing {
+    point: (f64, f64),
+    normal: (f64, f64),
+}
+
+impl Terrain {
+    /// One vertex per marching squares cell, as `(x, y)` in world space.
+    ///
+    /// Cells the contour passes through get the point that best fits the tangent lines
+    /// at their edge crossings, which lands on the corner where two straight walls meet.
+    /// The gradient of the scalar field gives the normals. Other cells keep their center.
+    pub fn dual_vertices(&self) -> Vec<Vec<(f64, f64)>> {
+        let mut vertices = Vec::with_capacity(self.height - 1);
+
+        for row in 0..self.height - 1 {
+            let mut vertex_row = Vec::with_capacity(self.width - 1);
+
+            for col in 0..self.width - 1 {
+                let (x_0, y_0) = self.sample_to_world(row, col);
+                let crossings = self.cell_crossings(row, col);
+
+                let vertex = if crossings.is_empty() {
+                    (x_0 + 0.5, y_0 + 0.5)
+                } else {
+                    let (x, y) = solve_vertex(&crossings);
+                    (x.clamp(x_0, x_0 + 1.0), y.clamp(y_0, y_0 + 1.0))
+                };
+                vertex_row.push(vertex);
+            }
+
+            vertices.push(vertex_row);
+        }
+
+        vertices
+    }
+
+    /// Dual contour as `[x_0, y_0, x_1, y_1]` segments in world space, oriented like
+    /// `contour_segments` with the rock on their right.
+    ///
+    /// Every grid edge between a solid and an empty sample is crossed by one segment
+    /// joining the vertices of the two cells on either side of it.
+    pub fn dual_contour_segments(&self) -> Vec<[f64; 4]> {
+        let vertices = self.dual_vertices();
+        let mut segments = Vec::new();
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let solid = self.is_solid_sample(row, col);
+
+                // Horizontal edge to the right, between the cells above and below it
+                if col + 1 < self.width && row > 0 && row + 1 < self.height {
+                    let right_solid = self.is_solid_sample(row, col + 1);
+                    if solid != right_solid {
+                        let above = vertices[row - 1][col];
+                        let below = vertices[row][col];
+                        let (start, end) = if solid {
+                            (above, below)
+                        } else {
+                            (below, above)
+                        };
+                        segments.push([start.0, start.1, end.0, end.1]);
+                    }
+                }
+
+                // Vertical edge downwards, between the cells left and right of it
+                if row + 1 < self.height && col > 0 && col + 1 < self.width {
+                    let below_solid = self.is_solid_sample(row + 1, col);
+                    if solid != below_solid {
+                        let left = vertices[row][col - 1];
+                        let right = vertices[row][col];
+                        let (start, end) = if solid { (right, left) } else { (left, right) };
+                        segments.push([start.0, start.1, end.0, end.1]);
+                    }
+                }
+            }
+        }
+
+        segments
+    }
+
+    /// Rock filled between the dual vertices as `[x_0, y_0, x_1, y_1, x_2, y_2]`
+    /// triangles in world space.
+    ///
+    /// Each solid sample owns the quad between the vertices of the four cells around
+    /// it, which is drawn as a fan around the sample. Samples on the border of the
+    /// terrain use the border itself where there is no cell.
+    pub fn dual_triangles(&self) -> Vec<[f64; 6]> {
+        self.dual_fans(|row, col| self.is_solid_sample(row, col))
+    }
+
+    /// The part of `dual_triangles` owned by samples of one material
+    pub fn dual_material_triangles(&self, material: u8) -> Vec<[f64; 6]> {
+        self.dual_fans(|row, col| {
+            self.is_solid_sample(row, col) && self.materials[row][col] == material
+        })
+    }
+
+    /// Fans around every sample `owned` returns true for
+    fn dual_fans(&self, owned: impl Fn(usize, usize) -> bool) -> Vec<[f64; 6]> {
+        let vertices = self.dual_vertices();
+        let (min_x, min_y) = self.sample_to_world(0, 0);
+        let (max_x, max_y) = self.sample_to_world(self.height - 1, self.width - 1);
+
+        // Vertex of the cell at `(row, col)`, cells off the grid are pushed onto its border
+        let vertex = |row: isize, col: isize| {
+            let cell_row = row.clamp(0, self.height as isize - 2) as usize;
+            let cell_col = col.clamp(0, self.width as isize - 2) as usize;
+            let (x, y) = vertices[cell_row][cell_col];
+
+            let x = if col < 0 {
+                min_x
+            } else if col > self.width as isize - 2 {
+                max_x
+            } else {
+                x
+            };
+            let y = if row < 0 {
+                min_y
+            } else if row > self.height as isize - 2 {
+                max_y
+            } else {
+                y
+            };
+            (x, y)
+        };
+
+        let mut triangles = Vec::new();
+        for row in 0..self.height {
+            for col in 0..self.width {
+                if !owned(row, col) {
+                    continue;
+                }
+
+                let (center_x, center_y) = self.sample_to_world(row, col);
+                let (row, col) = (row as isize, col as isize);
+                let corners = [
+                    vertex(row - 1, col - 1),
+                    vertex(row - 1, col),
+                    vertex(row, col),
+                    vertex(row, col - 1),
+                ];
+
+                for (index, &(x_0, y_0)) in corners.iter().enumerate() {
+                    let (x_1, y_1) = corners[(index + 1) % corners.len()];
+                    triangles.push([center_x, center_y, x_0, y_0, x_1, y_1]);
+                }
+            }
+        }
+
+        triangles
+    }
+
+    fn is_solid_sample(&self, row: usize, col: usize) -> bool {
+        self.scalar_field[row][col] > self.threshold()
+    }
+
+    /// Hermite data of the four edges of a cell
+    fn cell_crossings(&self, row: usize, col: usize) -> Vec<Crossing> {
+        let edges = [
+            ((row, col), (row, col + 1)),
+            ((row, col + 1), (row + 1, col + 1)),
+            ((row + 1, col), (row + 1, col + 1)),
+            ((row, col), (row + 1, col)),
+        ];
+
+        edges
+            .iter()
+            .filter(|&&(a, b)| self.is_solid_sample(a.0, a.1) != self.is_solid_sample(b.0, b.1))
+            .map(|&(a, b)| {
+                let value_a = self.scalar_field[a.0][a.1];
+                let value_b = self.scalar_field[b.0][b.1];
+                let t = ((self.threshold() - value_a) / (value_b - value_a)).clamp(0.0, 1.0);
+
+                let (x_a, y_a) = self.sample_to_world(a.0, a.1);
+                let (x_b, y_b) = self.sample_to_world(b.0, b.1);
+
+                let gradient_a = self.gradient(a.0, a.1);
+                let gradient_b = self.gradient(b.0, b.1);
+                let gradient = (
+                    gradient_a.0 + (gradient_b.0 - gradient_a.0) * t,
+                    gradient_a.1 + (gradient_b.1 - gradient_a.1) * t,
+                );
+
+                // Fall back to the edge direction where the field is flat
+                let length = (gradient.0 * gradient.0 + gradient.1 * gradient.1).sqrt();
+                let normal = if length > f64::EPSILON {
+                    (gradient.0 / length, gradient.1 / length)
+                } else {
+                    (x_b - x_a, y_b - y_a)
+                };
+
+                Crossing {
+                    point: (x_a + (x_b - x_a) * t, y_a + (y_b - y_a) * t),
+                    normal,
+                }
+            })
+            .collect()
+    }
+
+    /// Gradient of the scalar field at a sample as `(d/dx, d/dy)`.
+    ///
+    /// Takes the smaller of the one sided differences along each axis, and none where
+    /// they disagree in sign, so at a corner the normal of one side does not bleed into
+    /// the other. Samples on the border of the terrain only have one side.
+    fn gradient(&self, row: usize, col: usize) -> (f64, f64) {
+        let field = &self.scalar_field;
+        let value = field[row][col];
+
+        let backward_x = (col > 0).then(|| value - field[row][col - 1]);
+        let forward_x = (col + 1 < self.width).then(|| field[row][col + 1] - value);
+        let backward_y = (row > 0).then(|| value - field[row - 1][col]);
+        let forward_y = (row + 1 < self.height).then(|| field[row + 1][col] - value);
+
+        (minmod(backward_x, forward_x), minmod(backward_y, forward_y))
+    }
+}
+
+/// The smaller of two differences when they agree in sign, 0 otherwise
+fn minmod(a: Option<f64>, b: Option<f64>) -> f64 {
+    match (a, b) {
+        (Some(a), Some(b)) if a * b > 0.0 => {
+            if a.abs() < b.abs() {
+                a
+            } else {
+                b
+            }
+        }
+        (Some(_), Some(_)) => 0.0,
+        (Some(difference), None) | (None, Some(difference)) => difference,
+        (None, None) => 0.0,
+    }
+}
+
+/// Point closest to every tangent line through the crossings in the least squares sense
+fn solve_vertex(crossings: &[Crossing]) -> (f64, f64) {
+    let count = crossings.len() as f64;
+    let mass_point = crossings.iter().fold((0.0, 0.0), |sum, crossing| {
+        (
+            sum.0 + crossing.point.0 / count,
+            sum.1 + crossing.point.1 / count,
+        )
+    });
+
+    // Normal equations of the offset from the mass point, A^T A x = A^T b
+    let (mut a_00, mut a_01, mut a_11) = (MASS_POINT_WEIGHT, 0.0, MASS_POINT_WEIGHT);
+    let (mut b_0, mut b_1) = (0.0, 0.0);
+    for crossing in crossings {
+        let (n_x, n_y) = crossing.normal;
+        let distance =
+            n_x * (crossing.point.0 - mass_point.0) + n_y * (crossing.point.1 - mass_point.1);
+
+        a_00 += n_x * n_x;
+        a_01 += n_x * n_y;
+        a_11 += n_y * n_y;
+        b_0 += n_x * distance;
+        b_1 += n_y * distance;
+    }
+
+    let determinant = a_00 * a_11 - a_01 * a_01;
+    if determinant.abs() < f64::EPSILON {
+        return mass_point;
+    }
+
+    (
+        mass_point.0 + (a_11 * b_0 - a_01 * b_1) / determinant,
+        mass_point.1 + (a_00 * b_1 - a_01 * b_0) / determinant,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::ContourMode;
+
+    /// Rock in a box from `(4.2, 4.2)` to `(10.8, 9.8)`, the scalar field rising
+    /// linearly into each of its sides. Every corner is as far from the sides as the
+    /// solid sample next to it, so the crossings on both sides are exact.
+    fn box_terrain() -> Terrain {
+        let mut terrain = Terrain::from_scalar_field(vec![vec![0.0; 16]; 14]);
+        for row in 0..terrain.height {
+            for col in 0..terrain.width {
+                let (x, y) = terrain.sample_to_world(row, col);
+                let distance = (4.2 - x).max(x - 10.8).max(4.2 - y).max(y - 9.8);
+                terrain.scalar_field[row][col] =
+                    (terrain.threshold() - distance * 0.25).clamp(0.0, 1.0);
+            }
+        }
+        terrain.construct_index_grid();
+
+        terrain
+    }
+
+    fn near(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 0.05 && (a.1 - b.1).abs() < 0.05
+    }
+
+    #[test]
+    fn box_keeps_its_corners() {
+        let terrain = box_terrain();
+        let vertices = terrain.dual_vertices();
+
+        // Cells are offset by half a sample, the corners sit in these cells
+        let corners = [
+            ((3, 3), (4.2, 4.2)),
+            ((3, 10), (10.8, 4.2)),
+            ((9, 10), (10.8, 9.8)),
+            ((9, 3), (4.2, 9.8)),
+        ];
+        for ((row, col), corner) in corners {
+            assert!(near(vertices[row][col], corner), "{:?}", vertices[row][col]);
+        }
+    }
+
+    #[test]
+    fn dual_contour_turns_at_the_corners() {
+        let terrain = box_terrain();
+        let segments = terrain.dual_contour_segments();
+
+        // One segment arrives at every corner and another leaves it at a right angle
+        for corner in [(4.2, 4.2), (10.8, 4.2), (10.8, 9.8), (4.2, 9.8)] {
+            let arriving: Vec<_> = segments
+                .iter()
+                .filter(|segment| near((segment[2], segment[3]), corner))
+                .collect();
+            let leaving: Vec<_> = segments
+                .iter()
+                .filter(|segment| near((segment[0], segment[1]), corner))
+                .collect();
+            assert_eq!((arriving.len(), leaving.len()), (1, 1), "{:?}", corner);
+
+            let incoming = (
+                arriving[0][2] - arriving[0][0],
+                arriving[0][3] - arriving[0][1],
+            );
+            let outgoing = (leaving[0][2] - leaving[0][0], leaving[0][3] - leaving[0][1]);
+            assert!((incoming.0 * outgoing.0 + incoming.1 * outgoing.1).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn surfaces_follow_the_contour_mode() {
+        let mut terrain = box_terrain();
+        terrain.contour_mode = ContourMode::DualContouring;
+
+        let segments: Vec<[f64; 4]> = terrain
+            .surfaces(1.0, 1.0)
+            .map(|surface| surface.segment)
+            .collect();
+
+        assert_eq!(segments, terrain.dual_contour_segments());
+    }
+}
@@ -0,0 +1,357 @@
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    sdf::{point_distance, smooth_min, Shape},
+    terrain::Terrain,
+};
+
+/// Smallest partition in world units, one sample across, so the number of rooms stays
+/// bounded by the number of samples
+pub const MIN_LEAF_SIZE: f64 = 1.0;
+
+/// How the binary space partition splits the terrain and what it carves
+#[derive(Clone, Copy, Debug)]
+pub struct DungeonLayout {
+    /// Smallest side of a partition in world units, partitions are not split below
+    /// twice this. It is raised to `MIN_LEAF_SIZE`, and to the room size plus its margins
+    /// so every partition fits a room.
+    pub min_leaf_size: f64,
+    /// Smallest side of a room in world units, rooms only shrink below it when the
+    /// whole terrain is smaller
+    pub min_room_size: f64,
+    /// Rock left between a room and the border of its partition
+    pub room_margin: f64,
+    pub corridor_width: f64,
+    /// Distance in world units over which rooms and corridors blend into each other,
+    /// rounding off the corners where they meet
+    pub softness: f64,
+    /// How much of the existing scalar field shows through, 0 for plain boxes and 1
+    /// for no dungeon at all
+    pub noise_blend: f64,
+}
+
+/// Rooms of a 40 x 40 terrain roughed up by the cave noise underneath
+pub fn cave_dungeon() -> DungeonLayout {
+    DungeonLayout {
+        min_leaf_size: 9.0,
+        min_room_size: 3.0,
+        room_margin: 1.0,
+        corridor_width: 2.5,
+        softness: 1.5,
+        noise_blend: 0.3,
+    }
+}
+
+/// Axis aligned room in world space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Room {
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+}
+
+impl Room {
+    pub fn center(&self) -> (f64, f64) {
+        (
+            (self.min.0 + self.max.0) / 2.0,
+            (self.min.1 + self.max.1) / 2.0,
+        )
+    }
+
+    fn shape(&self) -> Shape {
+        Shape::Box {
+            center: self.center(),
+            half_size: (
+                (self.max.0 - self.min.0) / 2.0,
+                (self.max.1 - self.min.1) / 2.0,
+            ),
+            angle: 0.0,
+        }
+    }
+}
+
+impl Terrain {
+    /// Lay out rooms joined by corridors over the terrain.
+    ///
+    /// The terrain is split in two again and again at places picked by `seed`, a room is
+    /// placed in every partition and the two halves of each split are joined by an L-shaped
+    /// corridor between their closest rooms. Rooms and corridors are rasterized as a
+    /// distance field like `stamp` does, then blended with the existing scalar field by
+    /// `noise_blend`.
+    pub fn generate_dungeon(&mut self, layout: &DungeonLayout, seed: u64) -> Vec<Room> {
+        for (name, value) in [
+            ("min_room_size", layout.min_room_size),
+            ("room_margin", layout.room_margin),
+            ("corridor_width", layout.corridor_width),
+            ("softness", layout.softness),
+        ] {
+            assert!(
+                (0.0..f64::INFINITY).contains(&value),
+                "{} must be 0 or more, got {}",
+                name,
+                value
+            );
+        }
+        assert!(
+            (0.0..=1.0).contains(&layout.noise_blend),
+            "noise_blend must be between 0 and 1, got {}",
+            layout.noise_blend
+        );
+
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let (min_x, min_y) = self.sample_to_world(0, 0);
+        let (max_x, max_y) = self.sample_to_world(self.height - 1, self.width - 1);
+
+        // A partition of no size could be split forever, and one smaller than a room
+        // would squeeze it below its size
+        let room_size = layout.min_room_size + 2.0 * layout.room_margin;
+        let layout = &DungeonLayout {
+            min_leaf_size: layout.min_leaf_size.max(MIN_LEAF_SIZE).max(room_size),
+            ..*layout
+        };
+
+        let mut rooms = Vec::new();
+        let mut corridors = Vec::new();
+        partition(
+            Room {
+                min: (min_x, min_y),
+                max: (max_x, max_y),
+            },
+            layout,
+            &mut rng,
+            &mut rooms,
+            &mut corridors,
+        );
+        debug!("{} rooms and {} corridors", rooms.len(), corridors.len());
+
+        let shapes: Vec<Shape> = rooms.iter().map(Room::shape).chain(corridors).collect();
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let (x, y) = self.sample_to_world(row, col);
+
+                // Distance to the open dungeon, negative inside it
+                let distance = shapes
+                    .iter()
+                    .map(|shape| shape.distance(x, y))
+                    .reduce(|a, b| smooth_min(a, b, layout.softness))
+                    .unwrap_or(f64::INFINITY);
+
+                let dungeon = self.distance_to_scalar(-distance);
+                let scalar = &mut self.scalar_field[row][col];
+                *scalar = dungeon + (*scalar - dungeon) * layout.noise_blend;
+            }
+        }
+
+        self.construct_index_grid();
+
+        rooms
+    }
+}
+
+/// Split `area` until it is too small, placing a room in every leaf. Returns the
+/// indices of the rooms inside `area`.
+fn partition(
+    area: Room,
+    layout: &DungeonLayout,
+    rng: &mut StdRng,
+    rooms: &mut Vec<Room>,
+    corridors: &mut Vec<Shape>,
+) -> Vec<usize> {
+    let width = area.max.0 - area.min.0;
+    let height = area.max.1 - area.min.1;
+    let can_split_x = width >= 2.0 * layout.min_leaf_size;
+    let can_split_y = height >= 2.0 * layout.min_leaf_size;
+
+    if !can_split_x && !can_split_y {
+        rooms.push(place_room(area, layout, rng));
+        return vec![rooms.len() - 1];
+    }
+
+    // Cut across the longer side so partitions stay roughly square
+    let split_x = match (can_split_x, can_split_y) {
+        (true, false) => true,
+        (false, true) => false,
+        _ if width > height * 1.25 => true,
+        _ if height > width * 1.25 => false,
+        _ => rng.gen_bool(0.5),
+    };
+
+    let (first, second) = if split_x {
+        let x =
+            rng.gen_range(area.min.0 + layout.min_leaf_size..=area.max.0 - layout.min_leaf_size);
+        (
+            Room {
+                max: (x, area.max.1),
+                ..area
+            },
+            Room {
+                min: (x, area.min.1),
+                ..area
+            },
+        )
+    } else {
+        let y =
+            rng.gen_range(area.min.1 + layout.min_leaf_size..=area.max.1 - layout.min_leaf_size);
+        (
+            Room {
+                max: (area.max.0, y),
+                ..area
+            },
+            Room {
+                min: (area.min.0, y),
+                ..area
+            },
+        )
+    };
+
+    let first = partition(first, layout, rng, rooms, corridors);
+    let second = partition(second, layout, rng, rooms, corridors);
+
+    // Join the halves where they are closest, keeping corridors short
+    let (from, to) = first
+        .iter()
+        .flat_map(|&a| second.iter().map(move |&b| (a, b)))
+        .min_by(|&(a_0, b_0), &(a_1, b_1)| {
+            point_distance(rooms[a_0].center(), rooms[b_0].center())
+                .total_cmp(&point_distance(rooms[a_1].center(), rooms[b_1].center()))
+        })
+        .expect("both halves hold a room");
+    let start = rooms[from].center();
+    let end = rooms[to].center();
+    let corner = if rng.gen_bool(0.5) {
+        (end.0, start.1)
+    } else {
+        (start.0, end.1)
+    };
+
+    let radius = layout.corridor_width / 2.0;
+    corridors.push(Shape::Capsule {
+        start,
+        end: corner,
+        radius,
+    });
+    corridors.push(Shape::Capsule {
+        start: corner,
+        end,
+        radius,
+    });
+
+    first.into_iter().chain(second).collect()
+}
+
+/// Random room inside a leaf, keeping the margin to its border where there is space
+fn place_room(leaf: Room, layout: &DungeonLayout, rng: &mut StdRng) -> Room {
+    let mut side = |min: f64, max: f64| {
+        let space = (max - min - 2.0 * layout.room_margin).max(0.0);
+        let size = rng.gen_range(layout.min_room_size.min(space)..=space);
+        let start = min + (max - min - size) / 2.0;
+        let slack = (space - size) / 2.0;
+
+        let start = start + rng.gen_range(-slack..=slack);
+        (start, start + size)
+    };
+
+    let (min_x, max_x) = side(leaf.min.0, leaf.max.0);
+    let (min_y, max_y) = side(leaf.min.1, leaf.max.1);
+
+    Room {
+        min: (min_x, min_y),
+        max: (max_x, max_y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::{RegionKind, RegionMap};
+
+    #[test]
+    fn dungeon_rooms_are_connected() {
+        let layout = DungeonLayout {
+            noise_blend: 0.0,
+            ..cave_dungeon()
+        };
+
+        for seed in 0..8 {
+            let mut terrain = Terrain::new(40, 40);
+            let rooms = terrain.generate_dungeon(&layout, seed);
+            assert!(rooms.len() > 1);
+
+            let region_map = RegionMap::new(&terrain);
+            assert_eq!(region_map.regions_of_kind(RegionKind::Empty).count(), 1);
+            for room in &rooms {
+                let (x, y) = room.center();
+                assert!(!terrain.is_solid(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn dungeon_raises_tiny_leaves() {
+        for min_leaf_size in [0.0, -3.0, f64::NAN] {
+            let layout = DungeonLayout {
+                min_leaf_size,
+                ..cave_dungeon()
+            };
+
+            let rooms = Terrain::new(20, 20).generate_dungeon(&layout, 0);
+
+            assert!(!rooms.is_empty());
+            assert!(rooms.len() <= 19 * 19);
+        }
+    }
+
+    #[test]
+    fn dungeon_is_deterministic() {
+        let mut a = Terrain::new(40, 40);
+        let mut b = Terrain::new(40, 40);
+
+        let rooms = a.generate_dungeon(&cave_dungeon(), 4);
+
+        assert_eq!(b.generate_dungeon(&cave_dungeon(), 4), rooms);
+        assert_eq!(a.scalar_field, b.scalar_field);
+    }
+
+    #[test]
+    fn rooms_keep_their_size_and_margin() {
+        let layout = DungeonLayout {
+            min_leaf_size: 4.0,
+            min_room_size: 8.0,
+            room_margin: 2.0,
+            ..cave_dungeon()
+        };
+        let mut terrain = Terrain::new(60, 40);
+        let (max_x, max_y) = terrain.sample_to_world(39, 59);
+
+        let rooms = terrain.generate_dungeon(&layout, 2);
+
+        assert!(rooms.len() > 1);
+        for room in &rooms {
+            assert!(room.max.0 - room.min.0 >= 8.0 - 1e-9, "{:?}", room);
+            assert!(room.max.1 - room.min.1 >= 8.0 - 1e-9, "{:?}", room);
+            assert!(
+                room.min.0 >= 0.5 + 2.0 && room.min.1 >= 0.5 + 2.0,
+                "{:?}",
+                room
+            );
+            assert!(
+                room.max.0 <= max_x - 2.0 && room.max.1 <= max_y - 2.0,
+                "{:?}",
+                room
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "min_room_size")]
+    fn negative_room_size_is_rejected() {
+        let layout = DungeonLayout {
+            min_room_size: -1.0,
+            ..cave_dungeon()
+        };
+
+        Terrain::new(20, 20).generate_dungeon(&layout, 0);
+    }
+}
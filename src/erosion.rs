@@ -0,0 +1,436 @@
+use log::debug;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::terrain::Terrain;
+
+/// Droplets of water trickling down through the cave, wearing away the rock they run
+/// along and dropping the sediment where they come to rest
+#[derive(Clone, Copy, Debug)]
+pub struct HydraulicErosion {
+    pub droplets: usize,
+    /// Most steps a droplet takes, one sample each
+    pub lifetime: usize,
+    /// How much of its direction a droplet keeps every step, 0 follows the rock exactly
+    pub inertia: f64,
+    /// Sediment a droplet can carry per unit of speed and water
+    pub capacity: f64,
+    /// Fraction of the spare capacity taken from the rock every step
+    pub erosion_rate: f64,
+    /// Distance in samples over which a droplet wears the rock around it
+    pub radius: f64,
+    /// Fraction of the excess sediment dropped every step
+    pub deposition_rate: f64,
+    /// Fraction of the water lost every step
+    pub evaporation: f64,
+    /// Speed gained per sample fallen
+    pub gravity: f64,
+}
+
+/// Rock crumbling off steep walls and ceilings and settling further down
+#[derive(Clone, Copy, Debug)]
+pub struct ThermalErosion {
+    pub iterations: usize,
+    /// Largest difference in density between neighbouring samples that holds up
+    pub talus: f64,
+    /// Fraction of the excess over `talus` moved every iteration
+    pub strength: f64,
+}
+
+/// A few thousand droplets, enough to round off a 40 x 40 terrain
+pub fn dripping_water() -> HydraulicErosion {
+    HydraulicErosion {
+        droplets: 2000,
+        lifetime: 30,
+        inertia: 0.3,
+        capacity: 0.2,
+        erosion_rate: 0.3,
+        radius: 1.5,
+        deposition_rate: 0.3,
+        evaporation: 0.05,
+        gravity: 0.5,
+    }
+}
+
+pub fn crumbling_rock() -> ThermalErosion {
+    ThermalErosion {
+        iterations: 5,
+        talus: 0.4,
+        strength: 0.25,
+    }
+}
+
+/// Neighbours the rock can crumble into as `(row, col)` offsets, sideways and below
+const FALL_NEIGHBOURS: [(isize, isize); 5] = [(0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+
+impl Terrain {
+    /// Let water drip through the cave.
+    ///
+    /// Droplets start at open samples picked by `seed` and fall, sliding along the rock
+    /// when they meet it. While they move they pick up rock up to their capacity, which
+    /// grows with their speed, and drop what they cannot carry. A droplet stops when it can
+    /// no longer move down, leaving its sediment behind, so floors fill up while shafts and
+    /// the walls they run along are worn away.
+    pub fn erode_hydraulic(&mut self, erosion: &HydraulicErosion, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let open: Vec<(usize, usize)> = (0..self.height)
+            .flat_map(|row| (0..self.width).map(move |col| (row, col)))
+            .filter(|&(row, col)| self.scalar_field[row][col] <= self.threshold())
+            .collect();
+        debug!("dripping {} droplets", erosion.droplets);
+
+        for _ in 0..erosion.droplets {
+            let Some(&(row, col)) = open.choose(&mut rng) else {
+                break;
+            };
+            self.drip(erosion, (col as f64, row as f64));
+        }
+
+        self.construct_index_grid();
+    }
+
+    /// Let steep rock crumble until no neighbouring samples differ by more than the talus.
+    ///
+    /// Each iteration moves part of the excess from every sample to the less dense
+    /// samples beside and below it, all at once so the order of the samples does not
+    /// matter. Overhangs shed rock that falls until it lands on the floor.
+    pub fn erode_thermal(&mut self, erosion: &ThermalErosion) {
+        for _ in 0..erosion.iterations {
+            // Source, receiver and amount of every transfer, and what every sample receives
+            let mut transfers = Vec::new();
+            let mut incoming = vec![vec![0.0; self.width]; self.height];
+
+            for row in 0..self.height {
+                for col in 0..self.width {
+                    let density = self.scalar_field[row][col];
+
+                    let excesses: Vec<((usize, usize), f64)> = FALL_NEIGHBOURS
+                        .iter()
+                        .filter_map(|&(row_step, col_step)| {
+                            let next_row = row.checked_add_signed(row_step)?;
+                            let next_col = col.checked_add_signed(col_step)?;
+                            let next = *self.scalar_field.get(next_row)?.get(next_col)?;
+
+                            let excess = density - next - erosion.talus;
+                            (excess > 0.0).then_some(((next_row, next_col), excess))
+                        })
+                        .collect();
+
+                    let total: f64 = excesses.iter().map(|(_, excess)| excess).sum();
+                    if total == 0.0 {
+                        continue;
+                    }
+
+                    // Move at most half of the largest difference, so samples never swap
+                    let largest = excesses
+                        .iter()
+                        .map(|&(_, excess)| excess)
+                        .fold(0.0, f64::max);
+                    let moved = erosion.strength * largest / 2.0;
+
+                    for ((next_row, next_col), excess) in excesses {
+                        let amount = moved * excess / total;
+                        incoming[next_row][next_col] += amount;
+                        transfers.push(((row, col), (next_row, next_col), amount));
+                    }
+                }
+            }
+
+            // A sample fed from several sides only takes what fits, the rest stays put
+            let scales: Vec<Vec<f64>> = self
+                .scalar_field
+                .iter()
+                .zip(&incoming)
+                .map(|(scalars, incoming)| {
+                    scalars
+                        .iter()
+                        .zip(incoming)
+                        .map(|(scalar, &received)| {
+                            let headroom = 1.0 - scalar;
+                            if received > headroom {
+                                headroom / received
+                            } else {
+                                1.0
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+
+            for ((row, col), (next_row, next_col), amount) in transfers {
+                let amount = amount * scales[next_row][next_col];
+                self.scalar_field[row][col] -= amount;
+                self.scalar_field[next_row][next_col] += amount;
+            }
+        }
+
+        self.construct_index_grid();
+    }
+
+    /// Follow a single droplet from `(x, y)` in sample coordinates
+    fn drip(&mut self, erosion: &HydraulicErosion, start: (f64, f64)) {
+        let (mut x, mut y) = start;
+        let (mut direction_x, mut direction_y) = (0.0, 1.0);
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..erosion.lifetime {
+            // The gradient points into the rock, slide along it instead of falling into it
+            let (gradient_x, gradient_y) = self.density_gradient(x, y);
+            let length = (gradient_x * gradient_x + gradient_y * gradient_y).sqrt();
+            let (mut fall_x, mut fall_y) = (0.0, 1.0);
+            if length > f64::EPSILON && gradient_y > 0.0 {
+                let (normal_x, normal_y) = (gradient_x / length, gradient_y / length);
+                fall_x -= normal_x * normal_y;
+                fall_y -= normal_y * normal_y;
+            }
+
+            direction_x = direction_x * erosion.inertia + fall_x * (1.0 - erosion.inertia);
+            direction_y = direction_y * erosion.inertia + fall_y * (1.0 - erosion.inertia);
+            let length = (direction_x * direction_x + direction_y * direction_y).sqrt();
+            if length < f64::EPSILON {
+                break;
+            }
+            (direction_x, direction_y) = (direction_x / length, direction_y / length);
+
+            let (next_x, next_y) = (x + direction_x, y + direction_y);
+            let on_terrain = (0.0..=(self.width - 1) as f64).contains(&next_x)
+                && (0.0..=(self.height - 1) as f64).contains(&next_y);
+            if !on_terrain {
+                // Water leaving the terrain takes its sediment with it
+                return;
+            }
+            if self.density(next_x, next_y) > self.threshold() {
+                // Blocked by rock, the droplet pools here
+                break;
+            }
+
+            let capacity = (speed * water * erosion.capacity).max(0.0);
+            if sediment > capacity {
+                let dropped = (sediment - capacity) * erosion.deposition_rate;
+                sediment -= dropped - self.deposit(next_x, next_y, dropped);
+            } else {
+                let taken = (capacity - sediment) * erosion.erosion_rate;
+                sediment += self.erode(next_x, next_y, erosion.radius, taken);
+            }
+
+            speed = (speed * speed + direction_y * erosion.gravity)
+                .max(0.0)
+                .sqrt();
+            water *= 1.0 - erosion.evaporation;
+            (x, y) = (next_x, next_y);
+        }
+
+        // Sediment that does not fit where the droplet stopped piles up above it
+        while sediment > 0.0 && y >= 0.0 {
+            sediment = self.deposit(x, y, sediment);
+            y -= 1.0;
+        }
+    }
+
+    /// Corners of the cell around `(x, y)` in sample coordinates with their bilinear weights
+    fn cell_weights(&self, x: f64, y: f64) -> [((usize, usize), f64); 4] {
+        let col = (x.floor().max(0.0) as usize).min(self.width - 2);
+        let row = (y.floor().max(0.0) as usize).min(self.height - 2);
+        let (t_x, t_y) = (x - col as f64, y - row as f64);
+
+        [
+            ((row, col), (1.0 - t_x) * (1.0 - t_y)),
+            ((row, col + 1), t_x * (1.0 - t_y)),
+            ((row + 1, col), (1.0 - t_x) * t_y),
+            ((row + 1, col + 1), t_x * t_y),
+        ]
+    }
+
+    /// Bilinear interpolation of the scalar field in sample coordinates
+    fn density(&self, x: f64, y: f64) -> f64 {
+        self.cell_weights(x, y)
+            .iter()
+            .map(|&((row, col), weight)| self.scalar_field[row][col] * weight)
+            .sum()
+    }
+
+    fn density_gradient(&self, x: f64, y: f64) -> (f64, f64) {
+        let col = (x.floor().max(0.0) as usize).min(self.width - 2);
+        let row = (y.floor().max(0.0) as usize).min(self.height - 2);
+        let (t_x, t_y) = (x - col as f64, y - row as f64);
+        let field = &self.scalar_field;
+
+        (
+            (field[row][col + 1] - field[row][col]) * (1.0 - t_y)
+                + (field[row + 1][col + 1] - field[row + 1][col]) * t_y,
+            (field[row + 1][col] - field[row][col]) * (1.0 - t_x)
+                + (field[row + 1][col + 1] - field[row][col + 1]) * t_x,
+        )
+    }
+
+    /// Spread sediment over the samples around `(x, y)`, returns what did not fit into
+    /// samples that are already full
+    fn deposit(&mut self, x: f64, y: f64, amount: f64) -> f64 {
+        let mut left = 0.0;
+        for ((row, col), weight) in self.cell_weights(x, y) {
+            let scalar = &mut self.scalar_field[row][col];
+            let share = amount * weight;
+            let settled = share.min(1.0 - *scalar);
+            *scalar += settled;
+            left += share - settled;
+        }
+
+        left
+    }
+
+    /// Wear away up to `amount` from the rock within `radius` of `(x, y)`, returns how
+    /// much was taken
+    fn erode(&mut self, x: f64, y: f64, radius: f64, amount: f64) -> f64 {
+        let threshold = self.threshold();
+        let rows = (y - radius).ceil().max(0.0) as usize
+            ..=((y + radius).floor().max(0.0) as usize).min(self.height - 1);
+        let cols = (x - radius).ceil().max(0.0) as usize
+            ..=((x + radius).floor().max(0.0) as usize).min(self.width - 1);
+
+        // Only the rock the droplet runs along is worn, not the open cave around it,
+        // and the closer the rock the more of it goes
+        let weights: Vec<((usize, usize), f64)> = rows
+            .flat_map(|row| cols.clone().map(move |col| (row, col)))
+            .filter(|&(row, col)| self.scalar_field[row][col] > threshold)
+            .map(|(row, col)| {
+                let distance = ((col as f64 - x).powi(2) + (row as f64 - y).powi(2)).sqrt();
+                ((row, col), radius - distance)
+            })
+            .filter(|&(_, weight)| weight > 0.0)
+            .collect();
+        let rock_weight: f64 = weights.iter().map(|&(_, weight)| weight).sum();
+        if rock_weight <= 0.0 {
+            return 0.0;
+        }
+
+        let mut taken = 0.0;
+        for ((row, col), weight) in weights {
+            let scalar = &mut self.scalar_field[row][col];
+            let worn = (amount * weight / rock_weight).min(*scalar);
+            *scalar -= worn;
+            taken += worn;
+        }
+
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(terrain: &Terrain) -> f64 {
+        terrain.scalar_field.iter().flatten().sum()
+    }
+
+    #[test]
+    fn hydraulic_erosion_is_deterministic() {
+        let mut a = Terrain::new(40, 40);
+        let mut b = Terrain::new(40, 40);
+        let before = a.scalar_field.clone();
+
+        a.erode_hydraulic(&dripping_water(), 2);
+        b.erode_hydraulic(&dripping_water(), 2);
+
+        assert_eq!(a.scalar_field, b.scalar_field);
+        assert_ne!(a.scalar_field, before);
+    }
+
+    #[test]
+    fn thermal_erosion_moves_rock_down() {
+        // A single block of rock hanging in open cave
+        let mut terrain = Terrain::new(20, 20);
+        for row in 0..terrain.height {
+            for col in 0..terrain.width {
+                let solid = (5..8).contains(&row) && (8..12).contains(&col);
+                terrain.scalar_field[row][col] = if solid { 1.0 } else { 0.0 };
+            }
+        }
+        let before = total(&terrain);
+
+        terrain.erode_thermal(&crumbling_rock());
+
+        assert!((total(&terrain) - before).abs() < 1e-9);
+        assert!(terrain.scalar_field[8][10] > 0.0);
+        assert_eq!(terrain.scalar_field[4][10], 0.0);
+    }
+
+    #[test]
+    fn thermal_erosion_keeps_rock_that_does_not_fit() {
+        // Both ends of the top row crumble into the dip between them
+        let mut terrain =
+            Terrain::from_scalar_field(vec![vec![1.0, 0.5, 1.0], vec![1.0, 1.0, 1.0]]);
+        let before = total(&terrain);
+        let erosion = ThermalErosion {
+            iterations: 1,
+            talus: 0.0,
+            strength: 2.0,
+        };
+
+        terrain.erode_thermal(&erosion);
+
+        assert!((total(&terrain) - before).abs() < 1e-9);
+        assert_eq!(terrain.scalar_field[0], vec![0.75, 1.0, 0.75]);
+    }
+
+    /// Solid rock with a shaft one sample wide in column 10, open down to row 14
+    fn shaft() -> Terrain {
+        let mut scalar_field = vec![vec![1.0; 20]; 20];
+        for row in scalar_field.iter_mut().take(15) {
+            row[10] = 0.0;
+        }
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    #[test]
+    fn droplets_wear_shaft_walls_and_pool_at_the_bottom() {
+        let mut terrain = shaft();
+        let erosion = HydraulicErosion {
+            droplets: 200,
+            ..dripping_water()
+        };
+
+        terrain.erode_hydraulic(&erosion, 1);
+
+        for row in 2..12 {
+            assert!(
+                terrain.scalar_field[row][9] < 1.0,
+                "left wall at row {}",
+                row
+            );
+            assert!(
+                terrain.scalar_field[row][11] < 1.0,
+                "right wall at row {}",
+                row
+            );
+        }
+        assert!(terrain.scalar_field[14][10] > 0.0);
+    }
+
+    #[test]
+    fn droplets_keep_all_the_sediment() {
+        // A closed valley, droplets slide down its sides and pool against full rock
+        let mut scalar_field = vec![vec![0.0; 40]; 30];
+        for (row, scalars) in scalar_field.iter_mut().enumerate() {
+            for (col, scalar) in scalars.iter_mut().enumerate() {
+                let floor = 15.0 + (col as f64 - 20.0).abs() * 0.6;
+                if row as f64 >= floor || col == 0 || col == 39 {
+                    *scalar = 1.0;
+                }
+            }
+        }
+        let mut terrain = Terrain::from_scalar_field(scalar_field);
+        let before = total(&terrain);
+
+        terrain.erode_hydraulic(&dripping_water(), 3);
+
+        assert!((total(&terrain) - before).abs() < 1e-9);
+        assert!(terrain
+            .scalar_field
+            .iter()
+            .flatten()
+            .all(|&scalar| scalar <= 1.0));
+    }
+}
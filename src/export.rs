@@ -0,0 +1,160 @@
+use std::fmt::Write;
+
+use crate::{material::MATERIALS, terrain::Terrain};
+
+/// Terrain as plain text, geometry and materials together.
+///
+/// Sections start with a header line naming them and the number of lines that follow:
+///
+/// ```text
+/// terrain <width> <height> <threshold>
+/// materials <count>      one material per line as id, name and RGBA color
+/// scalar_field <height>  one row of samples per line
+/// material_field <height> one row of material IDs per line
+/// segments <count>       one contour segment per line as x_0 y_0 x_1 y_1 in world space
+/// material_segments <id> <count>  the contour of one material, repeated for every material
+/// ```
+///
+/// Values on a line are separated by spaces.
+pub fn export_terrain(terrain: &Terrain) -> String {
+    let mut text = String::new();
+
+    // Writing to a String never fails
+    writeln!(
+        text,
+        "terrain {} {} {}",
+        terrain.width,
+        terrain.height,
+        terrain.threshold()
+    )
+    .unwrap();
+
+    writeln!(text, "materials {}", MATERIALS.len()).unwrap();
+    for (id, material) in MATERIALS.iter().enumerate() {
+        let [r, g, b, a] = material.color;
+        writeln!(text, "{} {} {} {} {} {}", id, material.name, r, g, b, a).unwrap();
+    }
+
+    writeln!(text, "scalar_field {}", terrain.height).unwrap();
+    for scalars in &terrain.scalar_field {
+        writeln!(text, "{}", join(scalars)).unwrap();
+    }
+
+    writeln!(text, "material_field {}", terrain.height).unwrap();
+    for materials in &terrain.materials {
+        writeln!(text, "{}", join(materials)).unwrap();
+    }
+
+    let segments = terrain.contour_segments();
+    writeln!(text, "segments {}", segments.len()).unwrap();
+    for segment in &segments {
+        writeln!(text, "{}", join(segment)).unwrap();
+    }
+
+    for material in 0..MATERIALS.len() {
+        let segments = terrain.material_contour_segments(material as u8);
+        writeln!(text, "material_segments {} {}", material, segments.len()).unwrap();
+        for segment in &segments {
+            writeln!(text, "{}", join(segment)).unwrap();
+        }
+    }
+
+    text
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header line of a section and the lines that follow it
+    fn section<'a>(lines: &mut impl Iterator<Item = &'a str>) -> (Vec<&'a str>, Vec<&'a str>) {
+        let header: Vec<_> = lines.next().unwrap().split(' ').collect();
+        let count: usize = header.last().unwrap().parse().unwrap();
+        (header, lines.take(count).collect())
+    }
+
+    fn numbers(line: &str) -> Vec<f64> {
+        line.split(' ')
+            .map(|value| value.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn export_reads_back_as_the_terrain() {
+        let terrain = Terrain::from_scalar_field(vec![
+            vec![0.0, 0.0, 0.0],
+            vec![0.0, 0.75, 0.0],
+            vec![0.0, 0.5, 0.0],
+        ]);
+
+        let text = export_terrain(&terrain);
+        let mut lines = text.lines();
+
+        assert_eq!(lines.next(), Some("terrain 3 3 0.33"));
+
+        let (header, materials) = section(&mut lines);
+        assert_eq!(header, ["materials", "4"]);
+        assert_eq!(materials[0], "0 stone 71 45 35 255");
+        assert!(materials[3].starts_with("3 gold "));
+
+        let (header, rows) = section(&mut lines);
+        assert_eq!(header, ["scalar_field", "3"]);
+        let scalar_field: Vec<_> = rows.iter().map(|row| numbers(row)).collect();
+        assert_eq!(scalar_field, terrain.scalar_field);
+
+        let (header, rows) = section(&mut lines);
+        assert_eq!(header, ["material_field", "3"]);
+        assert!(rows.iter().all(|&row| row == "0 0 0"));
+
+        let (header, rows) = section(&mut lines);
+        assert_eq!(header[0], "segments");
+        let segments: Vec<_> = rows.iter().map(|row| numbers(row)).collect();
+        assert!(!segments.is_empty());
+        assert_eq!(segments, terrain.contour_segments());
+
+        for material in 0..MATERIALS.len() {
+            let (header, rows) = section(&mut lines);
+            assert_eq!(header[..2], ["material_segments", &material.to_string()]);
+
+            let segments: Vec<_> = rows.iter().map(|row| numbers(row)).collect();
+            assert_eq!(segments, terrain.material_contour_segments(material as u8));
+        }
+
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn ore_survives_the_export() {
+        let mut terrain = Terrain::new(60, 40);
+        terrain.generate_veins(&crate::material::default_veins(), 4);
+
+        let text = export_terrain(&terrain);
+        let mut lines = text.lines().skip(1);
+        section(&mut lines);
+        section(&mut lines);
+
+        let (_, rows) = section(&mut lines);
+        let materials: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|row| row.split(' ').map(|id| id.parse().unwrap()).collect())
+            .collect();
+        assert_eq!(materials, terrain.materials);
+        assert!(materials.iter().flatten().any(|&material| material != 0));
+
+        section(&mut lines);
+        for material in 0..MATERIALS.len() {
+            let (_, rows) = section(&mut lines);
+            let segments: Vec<_> = rows.iter().map(|row| numbers(row)).collect();
+            assert!(!segments.is_empty());
+            assert_eq!(segments, terrain.material_contour_segments(material as u8));
+        }
+    }
+}
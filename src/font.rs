@@ -214,6 +214,7 @@ pub fn get_char_symbol(c: char) -> [[u8; 5]; 5] {
     FONT[idx as usize]
 }
 
+#[allow(clippy::same_item_push)]
 pub fn scale_symbol(symbol: [[u8; 5]; 5], scale: usize) -> Vec<Vec<u8>> {
     let new_size = 5 * scale;
 
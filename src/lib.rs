@@ -0,0 +1,26 @@
+pub mod carve;
+pub mod chamber;
+pub mod chunk;
+pub mod collision;
+pub mod color;
+pub mod decoration;
+pub mod distance;
+pub mod dual;
+pub mod dungeon;
+pub mod erosion;
+pub mod export;
+pub mod font;
+pub mod material;
+pub mod modifier;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod path;
+pub mod region;
+pub mod render;
+pub mod sdf;
+pub mod surface;
+pub mod terrain;
+pub mod tunnel;
+pub mod volume;
+pub mod wfc;
+pub mod window;
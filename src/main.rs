@@ -1,21 +1,51 @@
-use color::{RED, WHITE};
-use render::{clear_frame, render_string, render_terrain, render_terrain_grid};
-use terrain::Terrain;
-use window::Window;
+use cave_terrarin_generator::{
+    chamber::cave_chambers,
+    chunk::ChunkedTerrain,
+    color::{BLUE, RED, WHITE},
+    decoration::{cave_decorations, export_decorations},
+    distance::DistanceField,
+    dungeon::cave_dungeon,
+    erosion::{crumbling_rock, dripping_water},
+    export::export_terrain,
+    material::default_veins,
+    modifier::strata_modifiers,
+    path::{Connectivity, Pathfinder},
+    region::RegionMap,
+    render::{
+        clear_frame, render_chunked_terrain, render_decorations, render_distance_field,
+        render_path, render_regions, render_string, render_surfaces, render_terrain,
+        render_terrain_grid, Viewport,
+    },
+    sdf::{Operation, Shape},
+    terrain::{ContourMode, ScalarField, Terrain},
+    tunnel::winding_tunnels,
+    volume::Volume,
+    wfc::TileRules,
+    window::Window,
+};
+use log::{error, info};
 use winit::{
     dpi::PhysicalPosition,
     event::{ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent},
 };
 
-mod color;
-mod font;
-mod render;
-mod terrain;
-mod window;
-
 fn main() {
     env_logger::init();
 
+    // Commands run without opening the editor
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        let result = match command.as_str() {
+            "erode" => erode_command(&args[1..]),
+            _ => Err(format!("unknown command {}", command)),
+        };
+        if let Err(message) = result {
+            error!("{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Rendering
     let screen_size = 1000;
     let (mut window, event_loop) = Window::new(screen_size, screen_size, 1);
@@ -27,27 +57,140 @@ fn main() {
     // World
     let world_width = 40;
     let world_height = 40;
-    let grid_size = screen_size / world_width;
     let mut terrain = Terrain::new(world_width, world_height);
+    let vein_seed = 1;
+    terrain.generate_veins(&default_veins(), vein_seed);
+    let terrain_file = "terrain.txt";
+    let mut modifiers = Vec::new();
+    let min_region_size = 8;
+
+    // Volume, shown one layer at a time
+    let volume = Volume::new(world_width, world_height, 40);
+    let mut volume_layer = 0;
+    let volume_file = "volume.obj";
+
+    // Open world
+    let mut open_world = ChunkedTerrain::new(16, 2, 3);
+    let mut camera_row = 0.0;
+    let mut camera_col = 0.0;
+    let camera_speed = 2.0;
+    let open_world_square_size = 25.0;
 
     let mut add_mode = true;
     let mut show_grid = false;
+    let mut show_regions = false;
+    let mut show_distance = false;
+    // Heatmap with the scalar field it was built from, rebuilt once the terrain is edited
+    let mut distance_cache: Option<(ScalarField, DistanceField)> = None;
+    let mut show_surfaces = false;
+    let mut show_decorations = false;
+    let mut open_world_mode = false;
+    let mut volume_mode = false;
+    let mut path_mode = false;
+    let mut shape_brush = false;
+
+    // Shape brush
+    let brush_radius = 2.0;
+    let brush_smoothness = 1.0;
+    // Blending again every frame would keep growing the shape, so only the first stamp
+    // of a stroke is smooth
+    let mut brush_stroke_started = false;
+
+    // Surfaces, diagonals count as floors and ceilings
+    let max_floor_angle = 50.0_f64.to_radians();
+    let max_ceiling_angle = 50.0_f64.to_radians();
+
+    // Decorations
+    let decoration_rules = cave_decorations();
+    let decoration_seed = 1;
+    let decoration_file = "decorations.csv";
+
+    // Dungeon, every press lays out a new one over fresh noise
+    let dungeon_layout = cave_dungeon();
+    let mut dungeon_seed = 1;
+
+    // Erosion, every press weathers the rock a little more
+    let hydraulic_erosion = dripping_water();
+    let thermal_erosion = crumbling_rock();
+    let mut erosion_seed = 1;
+
+    // Voronoi chambers, every press lays out a new cave
+    let chamber_layout = cave_chambers();
+    let mut chamber_seed = 1;
+
+    // Wave function collapse, every press synthesizes a new terrain from the one on screen
+    let mut synthesis_seed = 1;
+
+    // Winding tunnels, every press carves a new set
+    let tunnel_walks = winding_tunnels();
+    let mut walk_seed = 1;
+
+    // Explosions, right click blasts a hole at the cursor
+    let explosion_radius = 3.0;
+    let explosion_strength = 1.0;
 
-    let instruction_str = "a add mode\ns subtract mode\ng toggle grid\n";
+    // Path tool, clicks alternate between placing the start and the goal
+    let agent_radius = 0.75;
+    let mut path_start = None;
+    let mut path = Vec::new();
+
+    let instruction_str =
+        "a add mode\ns subtract mode\nk toggle circle brush\ng toggle grid\nb toggle sharp corners\nn toggle strata\nl generate dungeon\nu generate chambers\ni synthesize terrain like this one\nr toggle regions\nh toggle distance heatmap\nf toggle floors walls ceilings\nd toggle decorations\nx export decorations\ne export terrain\nc clean up small regions\nz erode\nt connect regions with tunnels\nw carve winding tunnels\np toggle path tool\nright click explosion\no toggle open world\nv toggle volume slice\ncomma period change slice layer\nm export volume mesh\narrows move camera\n";
 
     // Event loop
     event_loop.run(move |event, _, control_flow| match event {
         Event::MainEventsCleared => {
-            if mouse_pressed {
+            if open_world_mode {
+                open_world.stream(camera_row, camera_col);
+            }
+
+            // The 2D terrain is only edited while it is on screen
+            let editing = !open_world_mode && !volume_mode;
+
+            if mouse_pressed && open_world_mode && !volume_mode {
                 let (pixel_col, pixel_row) = window.screen.window_pos_to_pixel(mouse_pos);
+                let viewport = Viewport::centered_on(
+                    &window.screen,
+                    camera_row,
+                    camera_col,
+                    open_world_square_size,
+                );
 
-                let grid_row = pixel_row / grid_size;
-                let grid_col = pixel_col / grid_size;
+                let (world_row, world_col) = viewport.pixel_to_world(pixel_col, pixel_row);
+                let new_scalar = if add_mode { 1.0 } else { 0.0 };
+                open_world.modify_scalar_field(world_row, world_col, new_scalar);
+            } else if mouse_pressed && editing && !path_mode && shape_brush {
+                let (pixel_col, pixel_row) = window.screen.window_pos_to_pixel(mouse_pos);
+                let viewport = Viewport::fit(&window.screen, &terrain);
 
-                if add_mode {
-                    terrain.modify_scalar_field(grid_row, grid_col, 1.0);
+                let brush = Shape::Circle {
+                    center: viewport.pixel_to_point(pixel_col, pixel_row),
+                    radius: brush_radius,
+                };
+                let operation = if add_mode {
+                    Operation::Union
+                } else {
+                    Operation::Subtraction
+                };
+                let smoothness = if brush_stroke_started {
+                    0.0
                 } else {
-                    terrain.modify_scalar_field(grid_row, grid_col, 0.0);
+                    brush_smoothness
+                };
+                terrain.stamp(&brush, operation, smoothness);
+                brush_stroke_started = true;
+            } else if mouse_pressed && editing && !path_mode {
+                let (pixel_col, pixel_row) = window.screen.window_pos_to_pixel(mouse_pos);
+                let viewport = Viewport::fit(&window.screen, &terrain);
+
+                if let Some((grid_row, grid_col)) =
+                    viewport.pixel_to_sample(&terrain, pixel_col, pixel_row)
+                {
+                    if add_mode {
+                        terrain.modify_scalar_field(grid_row, grid_col, 1.0);
+                    } else {
+                        terrain.modify_scalar_field(grid_row, grid_col, 0.0);
+                    }
                 }
             }
 
@@ -55,10 +198,68 @@ fn main() {
         }
         Event::RedrawRequested(_) => {
             clear_frame(&mut window.screen, &[135, 142, 136, 255]);
+            let editing = !open_world_mode && !volume_mode;
 
-            render_terrain(&mut window.screen, &terrain, &[71, 45, 35, 255]);
+            if volume_mode {
+                let slice = volume.slice(volume_layer);
+                render_terrain(&mut window.screen, &slice, &[71, 45, 35, 255]);
+            } else if open_world_mode {
+                render_chunked_terrain(
+                    &mut window.screen,
+                    &open_world,
+                    camera_row,
+                    camera_col,
+                    open_world_square_size,
+                    &[71, 45, 35, 255],
+                );
+            } else if show_distance {
+                let edited = distance_cache
+                    .as_ref()
+                    .is_none_or(|(scalar_field, _)| *scalar_field != terrain.scalar_field);
+                if edited {
+                    let distance_field = DistanceField::new(&terrain, 2);
+                    distance_cache = Some((terrain.scalar_field.clone(), distance_field));
+                }
+
+                if let Some((_, distance_field)) = &distance_cache {
+                    render_distance_field(&mut window.screen, &terrain, distance_field);
+                }
+            } else {
+                render_terrain(&mut window.screen, &terrain, &[71, 45, 35, 255]);
+            }
 
-            if show_grid {
+            if show_regions && editing {
+                let region_map = RegionMap::new(&terrain);
+                render_regions(&mut window.screen, &terrain, &region_map);
+            }
+
+            if show_surfaces && editing {
+                let surfaces: Vec<_> = terrain
+                    .surfaces(max_floor_angle, max_ceiling_angle)
+                    .collect();
+                render_surfaces(&mut window.screen, &terrain, &surfaces);
+            }
+
+            if show_decorations && editing {
+                let decorations = terrain.place_decorations(
+                    &decoration_rules,
+                    max_floor_angle,
+                    max_ceiling_angle,
+                    decoration_seed,
+                );
+                render_decorations(
+                    &mut window.screen,
+                    &terrain,
+                    &decorations,
+                    &decoration_rules,
+                );
+            }
+
+            if path_mode && editing {
+                render_path(&mut window.screen, &terrain, &path, &BLUE);
+            }
+
+            if show_grid && editing {
                 render_terrain_grid(&mut window.screen, &terrain, &RED);
             }
 
@@ -71,11 +272,48 @@ fn main() {
             ..
         } => match window_event {
             WindowEvent::CloseRequested => control_flow.set_exit(),
+            WindowEvent::Resized(size) => window.resize(size),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                window.resize(*new_inner_size)
+            }
             WindowEvent::CursorMoved { position, .. } => {
                 mouse_pos = position;
             }
             WindowEvent::MouseInput { state, button, .. } => {
                 mouse_pressed = button == MouseButton::Left && state == ElementState::Pressed;
+                if mouse_pressed {
+                    brush_stroke_started = false;
+                }
+
+                let editing = !open_world_mode && !volume_mode;
+
+                if button == MouseButton::Right && state == ElementState::Pressed && editing {
+                    let (pixel_col, pixel_row) = window.screen.window_pos_to_pixel(mouse_pos);
+                    let viewport = Viewport::fit(&window.screen, &terrain);
+                    let point = viewport.pixel_to_point(pixel_col, pixel_row);
+
+                    terrain.carve_circle(point, explosion_radius, explosion_strength);
+                }
+
+                if mouse_pressed && path_mode && editing {
+                    let (pixel_col, pixel_row) = window.screen.window_pos_to_pixel(mouse_pos);
+                    let viewport = Viewport::fit(&window.screen, &terrain);
+                    let point = viewport.pixel_to_point(pixel_col, pixel_row);
+
+                    match path_start.take() {
+                        None => {
+                            path_start = Some(point);
+                            path = vec![point];
+                        }
+                        Some(start) => {
+                            let pathfinder =
+                                Pathfinder::new(&terrain, Connectivity::Eight, agent_radius);
+                            path = pathfinder
+                                .find_path(&terrain, start, point)
+                                .unwrap_or_default();
+                        }
+                    }
+                }
             }
             WindowEvent::KeyboardInput { input, .. } => {
                 if input.state != ElementState::Pressed {
@@ -85,7 +323,122 @@ fn main() {
                 match input.virtual_keycode {
                     Some(VirtualKeyCode::A) => add_mode = true,
                     Some(VirtualKeyCode::S) => add_mode = false,
+                    Some(VirtualKeyCode::K) => shape_brush = !shape_brush,
                     Some(VirtualKeyCode::G) => show_grid = !show_grid,
+                    Some(VirtualKeyCode::B) => {
+                        terrain.contour_mode = match terrain.contour_mode {
+                            ContourMode::MarchingSquares => ContourMode::DualContouring,
+                            ContourMode::DualContouring => ContourMode::MarchingSquares,
+                        };
+                    }
+                    Some(VirtualKeyCode::N) => {
+                        modifiers = if modifiers.is_empty() {
+                            strata_modifiers(0.0, world_height as f64)
+                        } else {
+                            Vec::new()
+                        };
+
+                        let contour_mode = terrain.contour_mode;
+                        terrain =
+                            Terrain::with_modifiers(world_width, world_height, 0, 0, &modifiers);
+                        terrain.contour_mode = contour_mode;
+                        terrain.generate_veins(&default_veins(), vein_seed);
+                        path_start = None;
+                        path.clear();
+                    }
+                    Some(VirtualKeyCode::L) => {
+                        let contour_mode = terrain.contour_mode;
+                        terrain =
+                            Terrain::with_modifiers(world_width, world_height, 0, 0, &modifiers);
+                        terrain.contour_mode = contour_mode;
+                        terrain.generate_dungeon(&dungeon_layout, dungeon_seed);
+                        terrain.generate_veins(&default_veins(), vein_seed);
+                        dungeon_seed += 1;
+                        path_start = None;
+                        path.clear();
+                    }
+                    Some(VirtualKeyCode::Z) => {
+                        terrain.erode_hydraulic(&hydraulic_erosion, erosion_seed);
+                        terrain.erode_thermal(&thermal_erosion);
+                        erosion_seed += 1;
+                    }
+                    Some(VirtualKeyCode::U) => {
+                        terrain.generate_chambers(&chamber_layout, chamber_seed);
+                        terrain.generate_veins(&default_veins(), vein_seed);
+                        chamber_seed += 1;
+                        path_start = None;
+                        path.clear();
+                    }
+                    Some(VirtualKeyCode::I) => {
+                        let rules = TileRules::learn(&terrain);
+                        match rules.synthesize(world_width, world_height, synthesis_seed) {
+                            Some(synthesized) => {
+                                let contour_mode = terrain.contour_mode;
+                                terrain = synthesized;
+                                terrain.contour_mode = contour_mode;
+                                terrain.generate_veins(&default_veins(), vein_seed);
+                                path_start = None;
+                                path.clear();
+                            }
+                            None => error!("failed to synthesize terrain, try again"),
+                        }
+                        synthesis_seed += 1;
+                    }
+                    Some(VirtualKeyCode::C) => {
+                        terrain.remove_small_regions(min_region_size, min_region_size);
+                    }
+                    Some(VirtualKeyCode::T) => {
+                        terrain.connect_regions(2.0, 3.0, 1);
+                    }
+                    Some(VirtualKeyCode::W) => {
+                        terrain.carve_walks(&tunnel_walks, walk_seed);
+                        walk_seed += 1;
+                    }
+                    Some(VirtualKeyCode::P) => {
+                        path_mode = !path_mode;
+                        path_start = None;
+                        path.clear();
+                    }
+                    Some(VirtualKeyCode::H) => show_distance = !show_distance,
+                    Some(VirtualKeyCode::R) => show_regions = !show_regions,
+                    Some(VirtualKeyCode::F) => show_surfaces = !show_surfaces,
+                    Some(VirtualKeyCode::D) => show_decorations = !show_decorations,
+                    Some(VirtualKeyCode::E) => {
+                        match std::fs::write(terrain_file, export_terrain(&terrain)) {
+                            Ok(()) => info!("exported terrain to {}", terrain_file),
+                            Err(error) => error!("failed to export terrain: {}", error),
+                        }
+                    }
+                    Some(VirtualKeyCode::X) => {
+                        let decorations = terrain.place_decorations(
+                            &decoration_rules,
+                            max_floor_angle,
+                            max_ceiling_angle,
+                            decoration_seed,
+                        );
+                        let csv = export_decorations(&decorations, &decoration_rules);
+
+                        match std::fs::write(decoration_file, csv) {
+                            Ok(()) => info!("exported {} decorations", decorations.len()),
+                            Err(error) => error!("failed to export decorations: {}", error),
+                        }
+                    }
+                    Some(VirtualKeyCode::O) => open_world_mode = !open_world_mode,
+                    Some(VirtualKeyCode::V) => volume_mode = !volume_mode,
+                    Some(VirtualKeyCode::Period) => {
+                        volume_layer = (volume_layer + 1).min(volume.depth - 1);
+                    }
+                    Some(VirtualKeyCode::Comma) => volume_layer = volume_layer.saturating_sub(1),
+                    Some(VirtualKeyCode::M) => {
+                        match std::fs::write(volume_file, volume.mesh().to_obj()) {
+                            Ok(()) => info!("exported volume mesh to {}", volume_file),
+                            Err(error) => error!("failed to export volume mesh: {}", error),
+                        }
+                    }
+                    Some(VirtualKeyCode::Up) => camera_row -= camera_speed,
+                    Some(VirtualKeyCode::Down) => camera_row += camera_speed,
+                    Some(VirtualKeyCode::Left) => camera_col -= camera_speed,
+                    Some(VirtualKeyCode::Right) => camera_col += camera_speed,
                     _ => {}
                 }
             }
@@ -94,3 +447,74 @@ fn main() {
         _ => {}
     });
 }
+
+/// Generate terrain, weather it and export it to `terrain.txt`.
+///
+/// Takes `name=value` options overriding the size, the seed and the erosion settings.
+fn erode_command(options: &[String]) -> Result<(), String> {
+    let (mut width, mut height, mut seed) = (40, 40, 1);
+    let mut hydraulic = dripping_water();
+    let mut thermal = crumbling_rock();
+
+    for option in options {
+        let (name, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=value, got {}", option))?;
+
+        match name {
+            "width" => width = parse_option(name, value)?,
+            "height" => height = parse_option(name, value)?,
+            "seed" => seed = parse_option(name, value)?,
+            "droplets" => hydraulic.droplets = parse_option(name, value)?,
+            "lifetime" => hydraulic.lifetime = parse_option(name, value)?,
+            "erosion_rate" => hydraulic.erosion_rate = parse_option(name, value)?,
+            "deposition_rate" => hydraulic.deposition_rate = parse_option(name, value)?,
+            "iterations" => thermal.iterations = parse_option(name, value)?,
+            "talus" => thermal.talus = parse_option(name, value)?,
+            "strength" => thermal.strength = parse_option(name, value)?,
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+
+    // A terrain needs at least one cell to hold a contour
+    if width < 2 || height < 2 {
+        return Err(format!(
+            "terrain must be at least 2 x 2 samples, got {} x {}",
+            width, height
+        ));
+    }
+    check_range("erosion_rate", hydraulic.erosion_rate, 0.0, 1.0)?;
+    check_range("deposition_rate", hydraulic.deposition_rate, 0.0, 1.0)?;
+    check_range("talus", thermal.talus, 0.0, f64::INFINITY)?;
+    // Above 2 a sample could give away more than the difference to its neighbour
+    check_range("strength", thermal.strength, 0.0, 2.0)?;
+
+    let mut terrain = Terrain::new(width, height);
+    terrain.generate_veins(&default_veins(), 1);
+    terrain.erode_hydraulic(&hydraulic, seed);
+    terrain.erode_thermal(&thermal);
+
+    let terrain_file = "terrain.txt";
+    std::fs::write(terrain_file, export_terrain(&terrain))
+        .map_err(|error| format!("failed to export terrain: {}", error))?;
+    info!("exported eroded terrain to {}", terrain_file);
+
+    Ok(())
+}
+
+fn check_range(name: &str, value: f64, min: f64, max: f64) -> Result<(), String> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} must be between {} and {}, got {}",
+            name, min, max, value
+        ))
+    }
+}
+
+fn parse_option<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
@@ -0,0 +1,231 @@
+use log::debug;
+use noise::{NoiseFn, Perlin};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{color::Color, terrain::Terrain};
+
+pub struct Material {
+    pub name: &'static str,
+    pub color: Color,
+}
+
+/// Plain rock, every solid sample starts out as stone
+pub const STONE: u8 = 0;
+pub const COAL: u8 = 1;
+pub const IRON: u8 = 2;
+pub const GOLD: u8 = 3;
+
+/// Every material, indexed by material ID
+pub const MATERIALS: [Material; 4] = [
+    Material {
+        name: "stone",
+        color: [71, 45, 35, 255],
+    },
+    Material {
+        name: "coal",
+        color: [30, 30, 34, 255],
+    },
+    Material {
+        name: "iron",
+        color: [168, 112, 84, 255],
+    },
+    Material {
+        name: "gold",
+        color: [230, 190, 60, 255],
+    },
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum VeinShape {
+    /// Thin bands along the zero crossings of Perlin noise, `scale` is the noise frequency
+    /// per sample and `width` how close to zero the noise has to be
+    Noise { scale: f64, width: f64 },
+    /// Random walks through the rock, turning by up to `turn_rate` radians per step and
+    /// painting a disc of `radius` samples
+    Worm {
+        count: usize,
+        length: usize,
+        radius: f64,
+        turn_rate: f64,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VeinRule {
+    pub material: u8,
+    pub shape: VeinShape,
+}
+
+/// Wide coal seams, iron worms and rare thin gold veins
+pub fn default_veins() -> Vec<VeinRule> {
+    vec![
+        VeinRule {
+            material: COAL,
+            shape: VeinShape::Noise {
+                scale: 0.08,
+                width: 0.06,
+            },
+        },
+        VeinRule {
+            material: IRON,
+            shape: VeinShape::Worm {
+                count: 4,
+                length: 20,
+                radius: 0.9,
+                turn_rate: 0.6,
+            },
+        },
+        VeinRule {
+            material: GOLD,
+            shape: VeinShape::Noise {
+                scale: 0.2,
+                width: 0.02,
+            },
+        },
+    ]
+}
+
+impl Terrain {
+    /// Fill the rock with veins of ore, later rules are painted over earlier ones.
+    ///
+    /// Every sample is turned back to stone first, so no ore is left behind from an
+    /// earlier layout. Only samples that are solid when this is called get a vein.
+    pub fn generate_veins(&mut self, rules: &[VeinRule], seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        for materials in &mut self.materials {
+            materials.fill(STONE);
+        }
+
+        for rule in rules {
+            debug!(
+                "generating {} veins",
+                MATERIALS[rule.material as usize].name
+            );
+
+            match rule.shape {
+                VeinShape::Noise { scale, width } => {
+                    let perlin = Perlin::new(rng.gen());
+
+                    for row in 0..self.height {
+                        for col in 0..self.width {
+                            let noise = perlin.get([col as f64 * scale, row as f64 * scale]);
+                            if noise.abs() < width {
+                                self.paint_material(row, col, rule.material);
+                            }
+                        }
+                    }
+                }
+                VeinShape::Worm {
+                    count,
+                    length,
+                    radius,
+                    turn_rate,
+                } => {
+                    for _ in 0..count {
+                        let mut row = rng.gen_range(0.0..self.height as f64);
+                        let mut col = rng.gen_range(0.0..self.width as f64);
+                        let mut heading: f64 = rng.gen_range(0.0..std::f64::consts::TAU);
+
+                        for _ in 0..length {
+                            self.paint_material_disc(row, col, radius, rule.material);
+
+                            heading += rng.gen_range(-turn_rate..=turn_rate);
+                            row += heading.sin();
+                            col += heading.cos();
+
+                            let on_terrain = (0.0..self.height as f64).contains(&row)
+                                && (0.0..self.width as f64).contains(&col);
+                            if !on_terrain {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        self.construct_index_grid();
+    }
+
+    fn paint_material(&mut self, row: usize, col: usize, material: u8) {
+        if self.scalar_field[row][col] > self.threshold() {
+            self.materials[row][col] = material;
+        }
+    }
+
+    fn paint_material_disc(&mut self, row: f64, col: f64, radius: f64, material: u8) {
+        let row_start = (row - radius).floor().max(0.0) as usize;
+        let col_start = (col - radius).floor().max(0.0) as usize;
+        let row_end = ((row + radius).ceil().max(0.0) as usize).min(self.height - 1);
+        let col_end = ((col + radius).ceil().max(0.0) as usize).min(self.width - 1);
+
+        for sample_row in row_start..=row_end {
+            for sample_col in col_start..=col_end {
+                let row_distance = sample_row as f64 - row;
+                let col_distance = sample_col as f64 - col;
+
+                if row_distance * row_distance + col_distance * col_distance <= radius * radius {
+                    self.paint_material(sample_row, sample_col, material);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn veined_terrain(seed: u64) -> Terrain {
+        let mut terrain = Terrain::new(60, 40);
+        terrain.generate_veins(&default_veins(), seed);
+        terrain
+    }
+
+    #[test]
+    fn veins_only_paint_rock() {
+        let terrain = veined_terrain(4);
+
+        let mut painted = [0; MATERIALS.len()];
+        for (row, materials) in terrain.materials.iter().enumerate() {
+            for (col, &material) in materials.iter().enumerate() {
+                painted[material as usize] += 1;
+                if material != STONE {
+                    assert!(terrain.scalar_field[row][col] > terrain.threshold());
+                }
+            }
+        }
+
+        for material in [COAL, IRON, GOLD] {
+            assert!(
+                painted[material as usize] > 0,
+                "no {}",
+                MATERIALS[material as usize].name
+            );
+        }
+    }
+
+    #[test]
+    fn veins_replace_the_ore_of_the_last_layout() {
+        let mut terrain = veined_terrain(4);
+
+        // Hollow out the whole terrain, nothing is left to hold ore
+        for scalars in &mut terrain.scalar_field {
+            scalars.fill(0.0);
+        }
+        terrain.generate_veins(&default_veins(), 4);
+
+        assert!(terrain
+            .materials
+            .iter()
+            .flatten()
+            .all(|&material| material == STONE));
+    }
+
+    #[test]
+    fn veins_depend_only_on_the_seed() {
+        assert_eq!(veined_terrain(4).materials, veined_terrain(4).materials);
+        assert_ne!(veined_terrain(4).materials, veined_terrain(5).materials);
+    }
+}
@@ -0,0 +1,293 @@
+use noise::{NoiseFn, Perlin};
+
+/// A change to the terrain noise, applied to every sample before it is thresholded.
+///
+/// Modifiers stack in the order they are given. `DomainWarp` and `Anisotropic` move the
+/// point the noise is sampled at, each one working on the point left by the ones before
+/// it. `VerticalGradient` shifts the sampled value by the world row of the sample, so
+/// it always follows the real depth however the noise was warped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseModifier {
+    /// Push the sample point around by a second noise, which bends the caves into
+    /// flowing shapes. `strength` is the largest push in samples and `scale` the
+    /// frequency of the push noise per sample.
+    DomainWarp {
+        strength: f64,
+        scale: f64,
+        seed: u32,
+    },
+    /// Stretch the caves by `stretch_x` horizontally and `stretch_y` vertically, values
+    /// above 1 give longer features along that axis. Both must be above 0.
+    Anisotropic { stretch_x: f64, stretch_y: f64 },
+    /// Add `top_bias` at `top_row` blending to `bottom_bias` at `bottom_row`, positive
+    /// values give more rock. Rows outside the range keep the bias of the closest end.
+    VerticalGradient {
+        top_row: f64,
+        bottom_row: f64,
+        top_bias: f64,
+        bottom_bias: f64,
+    },
+}
+
+/// Strata running sideways with more rock towards the surface at `top_row` and the
+/// bottom at `bottom_row`, leaving the open caves in between
+pub fn strata_modifiers(top_row: f64, bottom_row: f64) -> Vec<NoiseModifier> {
+    let middle_row = (top_row + bottom_row) / 2.0;
+
+    vec![
+        NoiseModifier::Anisotropic {
+            stretch_x: 4.0,
+            stretch_y: 1.5,
+        },
+        NoiseModifier::DomainWarp {
+            strength: 2.0,
+            scale: 0.15,
+            seed: 2,
+        },
+        NoiseModifier::VerticalGradient {
+            top_row,
+            bottom_row: middle_row,
+            top_bias: 0.15,
+            bottom_bias: 0.0,
+        },
+        NoiseModifier::VerticalGradient {
+            top_row: middle_row,
+            bottom_row,
+            top_bias: 0.0,
+            bottom_bias: 0.1,
+        },
+    ]
+}
+
+/// The terrain noise with a stack of modifiers
+pub struct TerrainNoise<'a> {
+    perlin: Perlin,
+    modifiers: &'a [NoiseModifier],
+    /// Push noise of every `DomainWarp`, in the order they appear in `modifiers`
+    warps: Vec<Perlin>,
+}
+
+impl<'a> TerrainNoise<'a> {
+    /// Panics when a modifier would turn the noise into NaN or infinity, such as a
+    /// stretch of 0
+    pub fn new(modifiers: &'a [NoiseModifier]) -> Self {
+        for modifier in modifiers {
+            let finite = match *modifier {
+                NoiseModifier::DomainWarp {
+                    strength, scale, ..
+                } => strength.is_finite() && scale.is_finite(),
+                NoiseModifier::Anisotropic {
+                    stretch_x,
+                    stretch_y,
+                } => {
+                    assert!(
+                        stretch_x > 0.0 && stretch_y > 0.0,
+                        "stretch must be above 0, got {} and {}",
+                        stretch_x,
+                        stretch_y
+                    );
+                    stretch_x.is_finite() && stretch_y.is_finite()
+                }
+                NoiseModifier::VerticalGradient {
+                    top_row,
+                    bottom_row,
+                    top_bias,
+                    bottom_bias,
+                } => [top_row, bottom_row, top_bias, bottom_bias]
+                    .iter()
+                    .all(|value| value.is_finite()),
+            };
+            assert!(
+                finite,
+                "modifier parameters must be finite, got {:?}",
+                modifier
+            );
+        }
+
+        let warps = modifiers
+            .iter()
+            .filter_map(|modifier| match *modifier {
+                NoiseModifier::DomainWarp { seed, .. } => Some(Perlin::new(seed)),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            perlin: Perlin::new(1),
+            modifiers,
+            warps,
+        }
+    }
+
+    /// Sample the noise at a world position, between 0 and 1
+    pub fn sample(&self, world_row: i64, world_col: i64) -> f64 {
+        // Perlin noise is zero on integer coordinates, sample at the center of each square instead
+        let mut x = world_col as f64 + 0.5;
+        let mut y = world_row as f64 + 0.5;
+        let mut bias = 0.0;
+
+        let mut warps = self.warps.iter();
+        for modifier in self.modifiers {
+            match *modifier {
+                NoiseModifier::DomainWarp {
+                    strength, scale, ..
+                } => {
+                    let warp = warps.next().expect("one push noise per domain warp");
+
+                    // Offset the second lookup so both axes are pushed independently
+                    let push_x = warp.get([x * scale, y * scale]);
+                    let push_y = warp.get([x * scale + 5.2, y * scale + 1.3]);
+                    x += push_x * strength;
+                    y += push_y * strength;
+                }
+                NoiseModifier::Anisotropic {
+                    stretch_x,
+                    stretch_y,
+                } => {
+                    x /= stretch_x;
+                    y /= stretch_y;
+                }
+                NoiseModifier::VerticalGradient {
+                    top_row,
+                    bottom_row,
+                    top_bias,
+                    bottom_bias,
+                } => {
+                    let row = world_row as f64;
+                    let t = if bottom_row == top_row {
+                        if row < top_row {
+                            0.0
+                        } else {
+                            1.0
+                        }
+                    } else {
+                        ((row - top_row) / (bottom_row - top_row)).clamp(0.0, 1.0)
+                    };
+                    bias += top_bias + (bottom_bias - top_bias) * t;
+                }
+            }
+        }
+
+        let value = self.perlin.get([x, y]) * 0.5 + 0.5;
+
+        // Leave unmodified noise untouched so existing terrain comes out the same
+        if self.modifiers.is_empty() {
+            value
+        } else {
+            (value + bias).clamp(0.0, 1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::ChunkedTerrain;
+
+    /// The noise as it was sampled before modifiers existed
+    fn unmodified(row: i64, col: i64) -> f64 {
+        Perlin::new(1).get([col as f64 + 0.5, row as f64 + 0.5]) * 0.5 + 0.5
+    }
+
+    #[test]
+    fn no_modifiers_keep_the_noise() {
+        let noise = TerrainNoise::new(&[]);
+
+        for row in -20..20 {
+            for col in -20..20 {
+                assert_eq!(noise.sample(row, col), unmodified(row, col));
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_gradient_adds_rock_towards_the_biased_end() {
+        let modifiers = [NoiseModifier::VerticalGradient {
+            top_row: 0.0,
+            bottom_row: 100.0,
+            top_bias: 0.0,
+            bottom_bias: 0.2,
+        }];
+        let noise = TerrainNoise::new(&modifiers);
+
+        for col in 0..20 {
+            assert_eq!(noise.sample(-10, col), unmodified(-10, col));
+            assert_eq!(noise.sample(0, col), unmodified(0, col));
+
+            let middle = (unmodified(50, col) + 0.1).clamp(0.0, 1.0);
+            assert!((noise.sample(50, col) - middle).abs() < 1e-12);
+
+            for row in [100, 150] {
+                let bottom = (unmodified(row, col) + 0.2).clamp(0.0, 1.0);
+                assert!((noise.sample(row, col) - bottom).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn modifiers_apply_in_order() {
+        let warp = NoiseModifier::DomainWarp {
+            strength: 2.0,
+            scale: 0.15,
+            seed: 2,
+        };
+        let stretch = NoiseModifier::Anisotropic {
+            stretch_x: 4.0,
+            stretch_y: 1.5,
+        };
+        let (perlin, push) = (Perlin::new(1), Perlin::new(2));
+        let warped = |x: f64, y: f64| {
+            (
+                x + push.get([x * 0.15, y * 0.15]) * 2.0,
+                y + push.get([x * 0.15 + 5.2, y * 0.15 + 1.3]) * 2.0,
+            )
+        };
+
+        let warp_first = [warp, stretch];
+        let stretch_first = [stretch, warp];
+        let (warp_first, stretch_first) = (
+            TerrainNoise::new(&warp_first),
+            TerrainNoise::new(&stretch_first),
+        );
+
+        for (row, col) in [(0, 0), (3, 17), (-8, 5)] {
+            let (x, y) = (col as f64 + 0.5, row as f64 + 0.5);
+
+            let (warp_x, warp_y) = warped(x, y);
+            let expected = perlin.get([warp_x / 4.0, warp_y / 1.5]) * 0.5 + 0.5;
+            assert_eq!(warp_first.sample(row, col), expected);
+
+            let (warp_x, warp_y) = warped(x / 4.0, y / 1.5);
+            let expected = perlin.get([warp_x, warp_y]) * 0.5 + 0.5;
+            assert_eq!(stretch_first.sample(row, col), expected);
+        }
+    }
+
+    #[test]
+    fn modified_chunks_match_across_seams() {
+        let mut world = ChunkedTerrain::new(16, 1, 2);
+        world.modifiers = strata_modifiers(-20.0, 20.0);
+        world.stream(0.0, 0.0);
+        let noise = TerrainNoise::new(&world.modifiers);
+
+        for (&coord, chunk) in world.chunks() {
+            let (origin_row, origin_col) = world.chunk_origin(coord);
+
+            for (row, scalars) in chunk.scalar_field.iter().enumerate() {
+                for (col, &scalar) in scalars.iter().enumerate() {
+                    let (row, col) = (origin_row + row as i64, origin_col + col as i64);
+                    assert_eq!(scalar, noise.sample(row, col));
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "stretch must be above 0")]
+    fn zero_stretch_is_rejected() {
+        TerrainNoise::new(&[NoiseModifier::Anisotropic {
+            stretch_x: 0.0,
+            stretch_y: 1.0,
+        }]);
+    }
+}
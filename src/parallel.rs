@@ -0,0 +1,195 @@
+//! Rayon counterparts of the serial generation passes in `terrain` and of triangle
+//! rasterization in `render`.
+//!
+//! Every sample, cell and pixel is computed by the same function as the serial path,
+//! only the rows are spread across threads, so the output is bit-identical.
+
+use rayon::prelude::*;
+
+use crate::{
+    modifier::{NoiseModifier, TerrainNoise},
+    render::fill_triangle,
+    terrain::{cell_index, IndexField, MaterialField, ScalarField},
+    window::Screen,
+};
+
+pub fn sample_noise(
+    width: usize,
+    height: usize,
+    origin_row: i64,
+    origin_col: i64,
+    modifiers: &[NoiseModifier],
+) -> ScalarField {
+    let noise = TerrainNoise::new(modifiers);
+
+    (0..height)
+        .into_par_iter()
+        .map(|row| {
+            (0..width)
+                .map(|col| noise.sample(origin_row + row as i64, origin_col + col as i64))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn threshold_field(scalar_field: &ScalarField, threshold: f64) -> IndexField {
+    scalar_field
+        .par_iter()
+        .map(|scalars| {
+            scalars
+                .iter()
+                .map(|&scalar| if scalar > threshold { 1 } else { 0 })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn threshold_material_field(
+    scalar_field: &ScalarField,
+    materials: &MaterialField,
+    threshold: f64,
+    material: u8,
+) -> IndexField {
+    scalar_field
+        .par_iter()
+        .zip(materials)
+        .map(|(scalars, sample_materials)| {
+            scalars
+                .iter()
+                .zip(sample_materials)
+                .map(|(&scalar, &sample_material)| {
+                    u8::from(scalar > threshold && sample_material == material)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn index_field(thresholded_field: &IndexField) -> IndexField {
+    let height = thresholded_field.len();
+    let width = thresholded_field[0].len();
+
+    (0..height - 1)
+        .into_par_iter()
+        .map(|row| {
+            (0..width - 1)
+                .map(|col| cell_index(thresholded_field, row, col))
+                .collect()
+        })
+        .collect()
+}
+
+/// Rows of the screen filled by one task when rendering in parallel
+const BAND_ROWS: usize = 16;
+
+pub fn render_triangles(screen: &mut Screen, triangles: &[[usize; 6]], color: &[u8; 4]) {
+    let (width, height) = (screen.width(), screen.height());
+
+    screen
+        .frame_mut()
+        .par_chunks_mut(width * 4 * BAND_ROWS)
+        .enumerate()
+        .for_each(|(band, frame)| {
+            let first_row = band * BAND_ROWS;
+            let last_row = first_row + BAND_ROWS;
+
+            for triangle in triangles {
+                let top = triangle[1].min(triangle[3]).min(triangle[5]);
+                let bottom = triangle[1].max(triangle[3]).max(triangle[5]);
+                if bottom < first_row || top >= last_row {
+                    continue;
+                }
+
+                fill_triangle(frame, width, height, first_row, *triangle, color);
+            }
+        });
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{default_veins, MATERIALS},
+        modifier::strata_modifiers,
+        render::{self, terrain_triangles, Viewport},
+        terrain::{self, Terrain},
+    };
+
+    const THRESHOLD: f64 = 0.33;
+
+    #[test]
+    fn sample_noise_matches_serial() {
+        let modifiers = strata_modifiers(0.0, 100.0);
+
+        assert_eq!(
+            sample_noise(100, 70, -20, 35, &[]),
+            terrain::sample_noise(100, 70, -20, 35, &[])
+        );
+        assert_eq!(
+            sample_noise(100, 70, -20, 35, &modifiers),
+            terrain::sample_noise(100, 70, -20, 35, &modifiers)
+        );
+    }
+
+    #[test]
+    fn threshold_field_matches_serial() {
+        let scalar_field = terrain::sample_noise(100, 70, 0, 0, &[]);
+
+        assert_eq!(
+            threshold_field(&scalar_field, THRESHOLD),
+            terrain::threshold_field(&scalar_field, THRESHOLD)
+        );
+    }
+
+    #[test]
+    fn threshold_material_field_matches_serial() {
+        let mut terrain = Terrain::new(100, 70);
+        terrain.generate_veins(&default_veins(), 2);
+
+        for material in 0..MATERIALS.len() as u8 {
+            let field = threshold_material_field(
+                &terrain.scalar_field,
+                &terrain.materials,
+                THRESHOLD,
+                material,
+            );
+
+            assert!(field.iter().flatten().any(|&solid| solid == 1));
+            assert_eq!(
+                field,
+                terrain::threshold_material_field(
+                    &terrain.scalar_field,
+                    &terrain.materials,
+                    THRESHOLD,
+                    material
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn index_field_matches_serial() {
+        let scalar_field = terrain::sample_noise(100, 70, 0, 0, &[]);
+        let thresholded_field = terrain::threshold_field(&scalar_field, THRESHOLD);
+
+        assert_eq!(
+            index_field(&thresholded_field),
+            terrain::index_field(&thresholded_field)
+        );
+    }
+
+    #[test]
+    fn render_triangles_matches_serial() {
+        let color = [71, 45, 35, 255];
+        let terrain = Terrain::new(64, 64);
+        // Not a multiple of the band height, so the last band is cut short
+        let mut serial_screen = Screen::headless(500, 500);
+        let mut parallel_screen = Screen::headless(500, 500);
+        let triangles = terrain_triangles(&terrain, &Viewport::fit(&serial_screen, &terrain));
+
+        render::render_triangles(&mut serial_screen, &triangles, &color);
+        render_triangles(&mut parallel_screen, &triangles, &color);
+
+        assert!(serial_screen.frame() == parallel_screen.frame());
+    }
+}
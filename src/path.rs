@@ -0,0 +1,280 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use log::debug;
+
+use crate::{distance::clearance_map, terrain::Terrain};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Move between samples sharing an edge
+    Four,
+    /// Also move diagonally, as long as the move does not cut a corner of rock
+    Eight,
+}
+
+/// A* search over the empty samples of a terrain.
+///
+/// Samples are walkable when they are more than `agent_radius` world units away
+/// from the nearest wall, so an agent of that size never clips the contour.
+pub struct Pathfinder {
+    width: usize,
+    height: usize,
+    connectivity: Connectivity,
+    walkable: Vec<Vec<bool>>,
+}
+
+impl Pathfinder {
+    pub fn new(terrain: &Terrain, connectivity: Connectivity, agent_radius: f64) -> Self {
+        let clearance = clearance_map(terrain);
+
+        let walkable = clearance
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&distance| distance > agent_radius)
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            width: terrain.width,
+            height: terrain.height,
+            connectivity,
+            walkable,
+        }
+    }
+
+    pub fn is_walkable(&self, row: usize, col: usize) -> bool {
+        self.walkable[row][col]
+    }
+
+    /// Shortest path between two world positions on `terrain`, as world positions of the
+    /// sample centers along the way. `None` when either end is blocked or unreachable.
+    pub fn find_path(
+        &self,
+        terrain: &Terrain,
+        start: (f64, f64),
+        goal: (f64, f64),
+    ) -> Option<Vec<(f64, f64)>> {
+        let start_sample = terrain.world_to_sample(start.0, start.1)?;
+        let goal_sample = terrain.world_to_sample(goal.0, goal.1)?;
+
+        let samples = self.find_sample_path(start_sample, goal_sample)?;
+
+        Some(
+            samples
+                .into_iter()
+                .map(|(row, col)| terrain.sample_to_world(row, col))
+                .collect(),
+        )
+    }
+
+    /// Shortest path between two samples as `(row, col)` pairs, both ends included
+    pub fn find_sample_path(
+        &self,
+        start: (usize, usize),
+        goal: (usize, usize),
+    ) -> Option<Vec<(usize, usize)>> {
+        if !self.walkable[start.0][start.1] || !self.walkable[goal.0][goal.1] {
+            return None;
+        }
+
+        let mut cost = vec![vec![f64::INFINITY; self.width]; self.height];
+        let mut came_from = vec![vec![None; self.width]; self.height];
+        let mut open = BinaryHeap::new();
+
+        cost[start.0][start.1] = 0.0;
+        open.push(OpenSample {
+            estimate: self.heuristic(start, goal),
+            sample: start,
+        });
+
+        while let Some(OpenSample { sample, estimate }) = open.pop() {
+            if sample == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while let Some(previous) = came_from[current.0][current.1] {
+                    path.push(previous);
+                    current = previous;
+                }
+                path.reverse();
+
+                debug!("found path of {} samples", path.len());
+                return Some(path);
+            }
+
+            // Skip stale entries left behind when a cheaper route was found
+            let sample_cost = cost[sample.0][sample.1];
+            if estimate > sample_cost + self.heuristic(sample, goal) {
+                continue;
+            }
+
+            for (neighbour, step_cost) in self.neighbours(sample) {
+                let new_cost = sample_cost + step_cost;
+                if new_cost >= cost[neighbour.0][neighbour.1] {
+                    continue;
+                }
+
+                cost[neighbour.0][neighbour.1] = new_cost;
+                came_from[neighbour.0][neighbour.1] = Some(sample);
+                open.push(OpenSample {
+                    estimate: new_cost + self.heuristic(neighbour, goal),
+                    sample: neighbour,
+                });
+            }
+        }
+
+        None
+    }
+
+    fn heuristic(&self, from: (usize, usize), to: (usize, usize)) -> f64 {
+        let row_distance = from.0.abs_diff(to.0) as f64;
+        let col_distance = from.1.abs_diff(to.1) as f64;
+
+        match self.connectivity {
+            Connectivity::Four => row_distance + col_distance,
+            // Octile distance, diagonal moves first then straight ones
+            Connectivity::Eight => {
+                let diagonal = row_distance.min(col_distance);
+                let straight = row_distance.max(col_distance) - diagonal;
+                diagonal * std::f64::consts::SQRT_2 + straight
+            }
+        }
+    }
+
+    fn neighbours(&self, (row, col): (usize, usize)) -> Vec<((usize, usize), f64)> {
+        let walkable = |row: isize, col: isize| {
+            row >= 0
+                && col >= 0
+                && (row as usize) < self.height
+                && (col as usize) < self.width
+                && self.walkable[row as usize][col as usize]
+        };
+
+        let (row, col) = (row as isize, col as isize);
+        let mut neighbours = Vec::with_capacity(8);
+
+        for (row_step, col_step) in [(-1, 0), (0, 1), (1, 0), (0, -1)] {
+            if walkable(row + row_step, col + col_step) {
+                neighbours.push((((row + row_step) as usize, (col + col_step) as usize), 1.0));
+            }
+        }
+
+        if self.connectivity == Connectivity::Eight {
+            for (row_step, col_step) in [(-1, -1), (-1, 1), (1, 1), (1, -1)] {
+                if walkable(row + row_step, col + col_step)
+                    && walkable(row + row_step, col)
+                    && walkable(row, col + col_step)
+                {
+                    neighbours.push((
+                        ((row + row_step) as usize, (col + col_step) as usize),
+                        std::f64::consts::SQRT_2,
+                    ));
+                }
+            }
+        }
+
+        neighbours
+    }
+}
+
+/// Entry of the A* open set, ordered so the binary heap pops the lowest estimate first
+struct OpenSample {
+    estimate: f64,
+    sample: (usize, usize),
+}
+
+impl PartialEq for OpenSample {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for OpenSample {}
+
+impl PartialOrd for OpenSample {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenSample {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20x20 cave split by a wall of rock down column 10, open in rows `gap`
+    fn wall_with_gap(gap: std::ops::Range<usize>) -> Terrain {
+        let mut scalar_field = vec![vec![0.0; 20]; 20];
+        for (row, scalars) in scalar_field.iter_mut().enumerate() {
+            if !gap.contains(&row) {
+                scalars[10] = 1.0;
+            }
+        }
+
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    fn is_connected(path: &[(usize, usize)], connectivity: Connectivity) -> bool {
+        path.windows(2).all(|step| {
+            let rows = step[0].0.abs_diff(step[1].0);
+            let cols = step[0].1.abs_diff(step[1].1);
+            match connectivity {
+                Connectivity::Four => rows + cols == 1,
+                Connectivity::Eight => rows.max(cols) == 1,
+            }
+        })
+    }
+
+    #[test]
+    fn path_goes_through_the_gap() {
+        let terrain = wall_with_gap(8..12);
+
+        for connectivity in [Connectivity::Four, Connectivity::Eight] {
+            let pathfinder = Pathfinder::new(&terrain, connectivity, 0.5);
+            let path = pathfinder.find_sample_path((2, 5), (2, 15)).unwrap();
+
+            assert_eq!(path.first(), Some(&(2, 5)));
+            assert_eq!(path.last(), Some(&(2, 15)));
+            assert!(is_connected(&path, connectivity));
+            assert!(path
+                .iter()
+                .all(|&(row, col)| pathfinder.is_walkable(row, col)));
+            assert!(path
+                .iter()
+                .any(|&(row, col)| col == 10 && (8..12).contains(&row)));
+        }
+    }
+
+    #[test]
+    fn path_respects_clearance() {
+        // The gap is four samples wide, its middle 1.5 world units from the rock
+        let terrain = wall_with_gap(8..12);
+
+        let narrow = Pathfinder::new(&terrain, Connectivity::Eight, 1.0);
+        assert!(narrow.find_sample_path((2, 5), (2, 15)).is_some());
+
+        let wide = Pathfinder::new(&terrain, Connectivity::Eight, 2.0);
+        assert!(wide.is_walkable(2, 5) && wide.is_walkable(2, 15));
+        assert_eq!(wide.find_sample_path((2, 5), (2, 15)), None);
+    }
+
+    #[test]
+    fn blocked_path_is_none() {
+        let terrain = wall_with_gap(0..0);
+        let pathfinder = Pathfinder::new(&terrain, Connectivity::Eight, 0.5);
+
+        // Across the wall, and starting inside it
+        assert_eq!(pathfinder.find_sample_path((2, 5), (2, 15)), None);
+        assert_eq!(pathfinder.find_sample_path((2, 10), (2, 15)), None);
+        assert_eq!(
+            pathfinder.find_path(&terrain, (5.5, 2.5), (15.5, 2.5)),
+            None
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+
+use log::debug;
+
+use crate::terrain::{IndexField, Terrain, JOINED_SADDLE_10, JOINED_SADDLE_5};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Open cave, samples at or below the threshold
+    Empty,
+    /// Rock, samples above the threshold
+    Solid,
+}
+
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub kind: RegionKind,
+    /// Number of samples in the region
+    pub size: usize,
+    /// `(min_row, min_col, max_row, max_col)`, inclusive
+    pub bounding_box: (usize, usize, usize, usize),
+    /// `(row, col)` mean of the samples in the region
+    pub centroid: (f64, f64),
+}
+
+/// Connected regions of the thresholded field.
+///
+/// Connectivity follows the marching squares contour: samples connect through edges,
+/// and through corners unless the cell between them is a saddle that keeps them apart.
+/// Diagonally touching rock is joined or split as decided by the terrain's
+/// `SaddlePolicy`, and the cave connects across the cell exactly when the rock does not.
+pub struct RegionMap {
+    /// Index into `regions` for every sample
+    pub labels: Vec<Vec<usize>>,
+    pub regions: Vec<Region>,
+}
+
+impl RegionMap {
+    pub fn new(terrain: &Terrain) -> Self {
+        let thresholded_field = terrain.thresholded_field();
+
+        let mut labels = vec![vec![usize::MAX; terrain.width]; terrain.height];
+        let mut regions = Vec::new();
+
+        for row in 0..terrain.height {
+            for col in 0..terrain.width {
+                if labels[row][col] != usize::MAX {
+                    continue;
+                }
+
+                let kind = if thresholded_field[row][col] == 1 {
+                    RegionKind::Solid
+                } else {
+                    RegionKind::Empty
+                };
+
+                let region = flood_fill(
+                    &thresholded_field,
+                    &terrain.index_grid,
+                    &mut labels,
+                    row,
+                    col,
+                    kind,
+                    regions.len(),
+                );
+                regions.push(region);
+            }
+        }
+        debug!("regions: {:?}", regions);
+
+        Self { labels, regions }
+    }
+
+    pub fn region_at(&self, row: usize, col: usize) -> &Region {
+        &self.regions[self.labels[row][col]]
+    }
+
+    pub fn regions_of_kind(&self, kind: RegionKind) -> impl Iterator<Item = (usize, &Region)> {
+        self.regions
+            .iter()
+            .enumerate()
+            .filter(move |(_, region)| region.kind == kind)
+    }
+}
+
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+];
+
+fn flood_fill(
+    thresholded_field: &[Vec<u8>],
+    index_grid: &IndexField,
+    labels: &mut [Vec<usize>],
+    start_row: usize,
+    start_col: usize,
+    kind: RegionKind,
+    label: usize,
+) -> Region {
+    let height = thresholded_field.len();
+    let width = thresholded_field[0].len();
+    let value = thresholded_field[start_row][start_col];
+
+    // Saddle cells whose diagonals do not connect samples of this kind
+    let separating_saddles = match kind {
+        RegionKind::Solid => [5, 10],
+        RegionKind::Empty => [JOINED_SADDLE_5, JOINED_SADDLE_10],
+    };
+
+    let mut size = 0;
+    let mut bounding_box = (start_row, start_col, start_row, start_col);
+    let mut row_sum = 0.0;
+    let mut col_sum = 0.0;
+
+    let mut queue = VecDeque::new();
+    labels[start_row][start_col] = label;
+    queue.push_back((start_row, start_col));
+
+    while let Some((row, col)) = queue.pop_front() {
+        size += 1;
+        row_sum += row as f64;
+        col_sum += col as f64;
+        bounding_box.0 = bounding_box.0.min(row);
+        bounding_box.1 = bounding_box.1.min(col);
+        bounding_box.2 = bounding_box.2.max(row);
+        bounding_box.3 = bounding_box.3.max(col);
+
+        for &(row_step, col_step) in &NEIGHBOURS {
+            let (Some(next_row), Some(next_col)) = (
+                row.checked_add_signed(row_step),
+                col.checked_add_signed(col_step),
+            ) else {
+                continue;
+            };
+
+            if next_row >= height || next_col >= width {
+                continue;
+            }
+
+            if labels[next_row][next_col] != usize::MAX
+                || thresholded_field[next_row][next_col] != value
+            {
+                continue;
+            }
+
+            // Outside of saddles a diagonal neighbour of the same kind is also reached
+            // through one of the edges, so only saddles need checking
+            let diagonal = row_step != 0 && col_step != 0;
+            if diagonal
+                && separating_saddles.contains(&index_grid[row.min(next_row)][col.min(next_col)])
+            {
+                continue;
+            }
+
+            labels[next_row][next_col] = label;
+            queue.push_back((next_row, next_col));
+        }
+    }
+
+    Region {
+        kind,
+        size,
+        bounding_box,
+        centroid: (row_sum / size as f64, col_sum / size as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8x8 cave with an L of rock down column 1 and along row 3, and a single sample of
+    /// rock at (6, 6)
+    fn l_terrain() -> Terrain {
+        let rock = [(1, 1), (2, 1), (3, 1), (3, 2), (3, 3), (6, 6)];
+        let mut scalar_field = vec![vec![0.0; 8]; 8];
+        for (row, col) in rock {
+            scalar_field[row][col] = 1.0;
+        }
+
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    #[test]
+    fn regions_are_measured() {
+        let region_map = RegionMap::new(&l_terrain());
+        assert_eq!(region_map.regions.len(), 3);
+
+        let cave = region_map.region_at(0, 0);
+        assert_eq!(cave.kind, RegionKind::Empty);
+        assert_eq!(cave.size, 58);
+        assert_eq!(cave.bounding_box, (0, 0, 7, 7));
+
+        let l = region_map.region_at(3, 3);
+        assert_eq!(l.kind, RegionKind::Solid);
+        assert_eq!(l.size, 5);
+        assert_eq!(l.bounding_box, (1, 1, 3, 3));
+        assert!((l.centroid.0 - 2.4).abs() < 1e-9 && (l.centroid.1 - 1.6).abs() < 1e-9);
+
+        let dot = region_map.region_at(6, 6);
+        assert_eq!(dot.kind, RegionKind::Solid);
+        assert_eq!(dot.size, 1);
+        assert_eq!(dot.bounding_box, (6, 6, 6, 6));
+        assert_eq!(dot.centroid, (6.0, 6.0));
+    }
+
+    #[test]
+    fn every_sample_is_labelled_with_its_kind() {
+        let terrain = l_terrain();
+        let region_map = RegionMap::new(&terrain);
+
+        for row in 0..terrain.height {
+            for col in 0..terrain.width {
+                let solid = terrain.scalar_field[row][col] > terrain.threshold();
+                let kind = region_map.region_at(row, col).kind;
+                assert_eq!(kind == RegionKind::Solid, solid, "({}, {})", row, col);
+            }
+        }
+        assert_eq!(region_map.regions_of_kind(RegionKind::Solid).count(), 2);
+    }
+}
@@ -1,9 +1,105 @@
+#[cfg(feature = "parallel")]
+use crate::parallel;
 use crate::{
+    chunk::ChunkedTerrain,
+    color::{distinct_color, BLUE, GREEN, RED},
+    decoration::{Decoration, DecorationRule},
+    distance::DistanceField,
     font::{get_char_symbol, scale_symbol},
-    terrain::Terrain,
+    material::{Material, MATERIALS},
+    region::RegionMap,
+    surface::{Surface, SurfaceKind},
+    terrain::{ContourMode, IndexField, Terrain},
     window::Screen,
 };
 
+/// Placement of the terrain on the screen.
+///
+/// The world is scaled uniformly to fit the screen and centered, leaving bars
+/// on the sides or top and bottom when the aspect ratios differ. Every sample
+/// of the scalar field owns one square of `square_size` pixels.
+pub struct Viewport {
+    pub offset_x: f64,
+    pub offset_y: f64,
+    pub square_size: f64,
+}
+
+impl Viewport {
+    pub fn fit(screen: &Screen, terrain: &Terrain) -> Self {
+        let world_aspect_ratio = terrain.width as f64 / terrain.height as f64;
+
+        let square_size = if screen.aspect_ratio() > world_aspect_ratio {
+            screen.height() as f64 / terrain.height as f64
+        } else {
+            screen.width() as f64 / terrain.width as f64
+        };
+
+        let offset_x = (screen.width() as f64 - square_size * terrain.width as f64) / 2.0;
+        let offset_y = (screen.height() as f64 - square_size * terrain.height as f64) / 2.0;
+
+        Self {
+            offset_x: offset_x.floor(),
+            offset_y: offset_y.floor(),
+            square_size,
+        }
+    }
+
+    /// Viewport over an unbounded world that puts the camera, in world samples, at the screen center
+    pub fn centered_on(
+        screen: &Screen,
+        camera_row: f64,
+        camera_col: f64,
+        square_size: f64,
+    ) -> Self {
+        Self {
+            offset_x: (screen.width() as f64 / 2.0 - camera_col * square_size).floor(),
+            offset_y: (screen.height() as f64 / 2.0 - camera_row * square_size).floor(),
+            square_size,
+        }
+    }
+
+    /// World sample under a pixel for viewports that are not bound to a single terrain
+    pub fn pixel_to_world(&self, x: usize, y: usize) -> (i64, i64) {
+        let col = (x as f64 - self.offset_x) / self.square_size;
+        let row = (y as f64 - self.offset_y) / self.square_size;
+
+        (row.floor() as i64, col.floor() as i64)
+    }
+
+    /// World position `(x, y)` under a pixel, see `Terrain::sample_to_world`
+    pub fn pixel_to_point(&self, x: usize, y: usize) -> (f64, f64) {
+        (
+            (x as f64 - self.offset_x) / self.square_size,
+            (y as f64 - self.offset_y) / self.square_size,
+        )
+    }
+
+    /// Screen position of the top left corner of a sample's square
+    pub fn sample_to_pixel(&self, row: f64, col: f64) -> (f64, f64) {
+        (
+            self.offset_x + col * self.square_size,
+            self.offset_y + row * self.square_size,
+        )
+    }
+
+    /// Returns the `(row, col)` of the sample under a pixel, or `None` in the letterbox
+    pub fn pixel_to_sample(&self, terrain: &Terrain, x: usize, y: usize) -> Option<(usize, usize)> {
+        let col = (x as f64 - self.offset_x) / self.square_size;
+        let row = (y as f64 - self.offset_y) / self.square_size;
+
+        if col < 0.0 || row < 0.0 {
+            return None;
+        }
+
+        let (row, col) = (row.floor() as usize, col.floor() as usize);
+        if row >= terrain.height || col >= terrain.width {
+            return None;
+        }
+
+        Some((row, col))
+    }
+}
+
 pub fn clear_frame(screen: &mut Screen, color: &[u8; 4]) {
     for pixel in screen.frame_mut().chunks_exact_mut(4) {
         pixel.copy_from_slice(color);
@@ -17,31 +113,30 @@ pub fn set_pixel(screen: &mut Screen, x: usize, y: usize, color: &[u8; 4]) {
     screen.frame_mut()[index..index + 4].copy_from_slice(color);
 }
 
-pub fn set_row(screen: &mut Screen, y: usize, color: &[u8; 4]) {
-    debug_assert!(y < screen.height());
+/// Mix a color into the pixel already on the screen, `alpha` of 1 replaces it
+pub fn blend_pixel(screen: &mut Screen, x: usize, y: usize, color: &[u8; 4], alpha: f64) {
+    debug_assert!(x < screen.width() && y < screen.height());
 
-    for x in 0..(screen.width() - 1) {
-        set_pixel(screen, x, y, color);
+    let index = (y * screen.width() + x) * 4;
+    let pixel = &mut screen.frame_mut()[index..index + 3];
+    for (channel, &new_channel) in pixel.iter_mut().zip(color) {
+        *channel = (*channel as f64 * (1.0 - alpha) + new_channel as f64 * alpha).round() as u8;
     }
 }
 
-pub fn set_col(screen: &mut Screen, x: usize, color: &[u8; 4]) {
-    debug_assert!(x < screen.width());
+pub fn set_row(screen: &mut Screen, y: usize, x_start: usize, x_end: usize, color: &[u8; 4]) {
+    debug_assert!(y < screen.height() && x_end < screen.width());
 
-    for y in 0..(screen.height() - 1) {
+    for x in x_start..=x_end {
         set_pixel(screen, x, y, color);
     }
 }
 
-pub fn render_grid(screen: &mut Screen, step_size: usize, color: &[u8; 4]) {
-    // Draw rows
-    for y in (0..screen.height() - 1).step_by(step_size) {
-        set_row(screen, y, color);
-    }
+pub fn set_col(screen: &mut Screen, x: usize, y_start: usize, y_end: usize, color: &[u8; 4]) {
+    debug_assert!(x < screen.width() && y_end < screen.height());
 
-    // Draw coloumns
-    for x in (0..screen.width() - 1).step_by(step_size) {
-        set_col(screen, x, color);
+    for y in y_start..=y_end {
+        set_pixel(screen, x, y, color);
     }
 }
 
@@ -61,7 +156,7 @@ pub fn render_square(screen: &mut Screen, x: usize, y: usize, size: usize, color
     }
 
     if y_1 >= screen.height() {
-        y_1 = screen.width() - 1;
+        y_1 = screen.height() - 1;
     }
 
     for row in y_0..y_1 {
@@ -73,25 +168,223 @@ pub fn render_square(screen: &mut Screen, x: usize, y: usize, size: usize, color
 
 pub fn render_scalar_field(screen: &mut Screen, terrain: &Terrain) {
     let field = &terrain.scalar_field;
+    let viewport = Viewport::fit(screen, terrain);
+
+    for (row, scalars) in field.iter().enumerate() {
+        for (col, &scalar) in scalars.iter().enumerate() {
+            let (x_0, y_0) = viewport.sample_to_pixel(row as f64, col as f64);
+            let (x_1, y_1) = viewport.sample_to_pixel(row as f64 + 1.0, col as f64 + 1.0);
+
+            let alpha = (scalar * 255.0).round() as u8;
+            let color = [alpha, alpha, alpha, 255];
 
-    let mut square_size = screen.height() / terrain.height;
-    if screen.width() < screen.height() {
-        square_size = screen.width() / terrain.width;
+            // Size each square from its rounded corners so neighbours tile without gaps
+            let (x, y) = (x_0.round() as usize, y_0.round() as usize);
+            let size = (x_1.round() as usize - x).max(y_1.round() as usize - y);
+
+            render_square(screen, x, y, size, &color)
+        }
     }
+}
 
-    for row in 0..terrain.height {
-        for col in 0..terrain.width {
-            let x = (col as f64 * square_size as f64).round() as usize;
-            let y = (row as f64 * square_size as f64).round() as usize;
+/// Heatmap of a distance field, white on the contour fading to red in rock and blue in the cave
+pub fn render_distance_field(
+    screen: &mut Screen,
+    terrain: &Terrain,
+    distance_field: &DistanceField,
+) {
+    let viewport = Viewport::fit(screen, terrain);
+    let point_size = viewport.square_size / distance_field.resolution as f64;
+
+    // Distance at which the colors are fully saturated
+    let range = 4.0;
+
+    for (row, distances) in distance_field.values.iter().enumerate() {
+        for (col, &distance) in distances.iter().enumerate() {
+            // Each point is the center of its own square, like the samples of the scalar field
+            let (x, y) = distance_field.point_to_world(row, col);
+            let (center_x, center_y) = viewport.sample_to_pixel(y, x);
+            let (x_0, y_0) = (center_x - point_size / 2.0, center_y - point_size / 2.0);
+            let (x_1, y_1) = (x_0 + point_size, y_0 + point_size);
+
+            let fade = (255.0 * (1.0 - (distance.abs() / range).min(1.0))).round() as u8;
+            let color = if distance < 0.0 {
+                [255, fade, fade, 255]
+            } else {
+                [fade, fade, 255, 255]
+            };
+
+            let (x, y) = (x_0.round().max(0.0) as usize, y_0.round().max(0.0) as usize);
+            let size = (x_1.round() as usize - x).max(y_1.round() as usize - y);
+
+            render_square(screen, x, y, size, &color)
+        }
+    }
+}
 
-            let alpha = (field[row][col] * 255.0).round() as u8;
-            let color = [alpha, alpha, alpha, 255];
+/// Tint every sample square with the color of its connected region
+pub fn render_regions(screen: &mut Screen, terrain: &Terrain, region_map: &RegionMap) {
+    let viewport = Viewport::fit(screen, terrain);
+
+    for (row, labels) in region_map.labels.iter().enumerate() {
+        for (col, &label) in labels.iter().enumerate() {
+            let (x_0, y_0) = viewport.sample_to_pixel(row as f64, col as f64);
+            let (x_1, y_1) = viewport.sample_to_pixel(row as f64 + 1.0, col as f64 + 1.0);
+
+            let color = distinct_color(label);
+
+            for y in y_0.round() as usize..(y_1.round() as usize).min(screen.height()) {
+                for x in x_0.round() as usize..(x_1.round() as usize).min(screen.width()) {
+                    blend_pixel(screen, x, y, &color, 0.4);
+                }
+            }
+        }
+    }
+}
+
+/// Bresenham line, pixels off the screen are skipped
+pub fn render_line(
+    screen: &mut Screen,
+    x_0: isize,
+    y_0: isize,
+    x_1: isize,
+    y_1: isize,
+    color: &[u8; 4],
+) {
+    let x_delta = (x_1 - x_0).abs();
+    let y_delta = -(y_1 - y_0).abs();
+    let x_step = if x_0 < x_1 { 1 } else { -1 };
+    let y_step = if y_0 < y_1 { 1 } else { -1 };
+
+    let (mut x, mut y) = (x_0, y_0);
+    let mut error = x_delta + y_delta;
+
+    loop {
+        if x >= 0 && y >= 0 && (x as usize) < screen.width() && (y as usize) < screen.height() {
+            set_pixel(screen, x as usize, y as usize, color);
+        }
+
+        if x == x_1 && y == y_1 {
+            break;
+        }
 
-            render_square(screen, x, y, square_size, &color)
+        let doubled_error = 2 * error;
+        if doubled_error >= y_delta {
+            error += y_delta;
+            x += x_step;
+        }
+        if doubled_error <= x_delta {
+            error += x_delta;
+            y += y_step;
+        }
+    }
+}
+
+/// Draw a path of world positions over the terrain, with a marker on each end
+pub fn render_path(screen: &mut Screen, terrain: &Terrain, path: &[(f64, f64)], color: &[u8; 4]) {
+    let viewport = Viewport::fit(screen, terrain);
+    let to_pixel = |&(x, y): &(f64, f64)| {
+        let (pixel_x, pixel_y) = viewport.sample_to_pixel(y, x);
+        (pixel_x.round() as isize, pixel_y.round() as isize)
+    };
+
+    for segment in path.windows(2) {
+        let (x_0, y_0) = to_pixel(&segment[0]);
+        let (x_1, y_1) = to_pixel(&segment[1]);
+        render_line(screen, x_0, y_0, x_1, y_1, color);
+    }
+
+    let marker_size = (viewport.square_size / 2.0).round().max(1.0) as usize;
+    for end in path.first().into_iter().chain(path.last()) {
+        let (x, y) = to_pixel(end);
+        let half = marker_size as isize / 2;
+        render_square(
+            screen,
+            (x - half).max(0) as usize,
+            (y - half).max(0) as usize,
+            marker_size,
+            color,
+        );
+    }
+}
+
+/// Draw the contour colored by surface, floors green, walls blue and ceilings red.
+///
+/// Lines are thickened into the cave along the normal so the tint stays visible
+/// over the terrain.
+pub fn render_surfaces(screen: &mut Screen, terrain: &Terrain, surfaces: &[Surface]) {
+    let viewport = Viewport::fit(screen, terrain);
+    let thickness = (viewport.square_size / 6.0).round().max(1.0) as usize;
+
+    for surface in surfaces {
+        let color = match surface.kind {
+            SurfaceKind::Floor => GREEN,
+            SurfaceKind::Wall => BLUE,
+            SurfaceKind::Ceiling => RED,
+        };
+
+        let [x_0, y_0, x_1, y_1] = surface.segment;
+        let (pixel_x_0, pixel_y_0) = viewport.sample_to_pixel(y_0, x_0);
+        let (pixel_x_1, pixel_y_1) = viewport.sample_to_pixel(y_1, x_1);
+
+        for offset in 0..thickness {
+            let (offset_x, offset_y) = (
+                surface.normal.0 * offset as f64,
+                surface.normal.1 * offset as f64,
+            );
+            render_line(
+                screen,
+                (pixel_x_0 + offset_x).round() as isize,
+                (pixel_y_0 + offset_y).round() as isize,
+                (pixel_x_1 + offset_x).round() as isize,
+                (pixel_y_1 + offset_y).round() as isize,
+                &color,
+            );
         }
     }
 }
 
+/// Draw every prop as a spike growing out of the contour, colored by its rule
+pub fn render_decorations(
+    screen: &mut Screen,
+    terrain: &Terrain,
+    decorations: &[Decoration],
+    rules: &[DecorationRule],
+) {
+    let viewport = Viewport::fit(screen, terrain);
+
+    for decoration in decorations {
+        let size = rules[decoration.rule].size;
+        let (x, y) = decoration.position;
+        let (normal_x, normal_y) = decoration.normal;
+
+        // Base across the surface, tip out along the normal
+        let half_base = size / 4.0;
+        let corners = [
+            (x - normal_y * half_base, y + normal_x * half_base),
+            (x + normal_y * half_base, y - normal_x * half_base),
+            (x + normal_x * size, y + normal_y * size),
+        ];
+
+        let mut vertices = [0; 6];
+        let mut on_screen = true;
+        for (index, &(corner_x, corner_y)) in corners.iter().enumerate() {
+            let (pixel_x, pixel_y) = viewport.sample_to_pixel(corner_y, corner_x);
+            on_screen &= pixel_x >= 0.0 && pixel_y >= 0.0;
+
+            vertices[index * 2] = pixel_x.round() as usize;
+            vertices[index * 2 + 1] = pixel_y.round() as usize;
+        }
+
+        if on_screen {
+            let (width, height) = (screen.width(), screen.height());
+            let color = distinct_color(decoration.rule);
+            fill_triangle(screen.frame_mut(), width, height, 0, vertices, &color);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn barycentric(
     x: f64,
     y: f64,
@@ -111,6 +404,7 @@ fn barycentric(
     (lambda1, lambda2, lambda3)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_triangle(
     screen: &mut Screen,
     x_0: usize,
@@ -120,80 +414,288 @@ pub fn render_triangle(
     x_2: usize,
     y_2: usize,
     color: &[u8; 4],
+) {
+    let (width, height) = (screen.width(), screen.height());
+    fill_triangle(
+        screen.frame_mut(),
+        width,
+        height,
+        0,
+        [x_0, y_0, x_1, y_1, x_2, y_2],
+        color,
+    );
+}
+
+/// Rasterize a triangle given as `[x_0, y_0, x_1, y_1, x_2, y_2]` into a frame.
+///
+/// `frame` holds the rows of a `width` x `height` screen starting at `first_row`,
+/// which lets bands of the screen be filled independently. Pixels outside of the
+/// band are skipped.
+pub(crate) fn fill_triangle(
+    frame: &mut [u8],
+    width: usize,
+    height: usize,
+    first_row: usize,
+    vertices: [usize; 6],
+    color: &[u8; 4],
 ) {
     // TODO Fix error with pixels missing on the left edge of the triangle.
     // I think this is a problem with rounding and floating point arithemtic.
 
+    let [x_0, y_0, x_1, y_1, x_2, y_2] = vertices;
+
     let col_start = x_0.min(x_1).min(x_2);
     let row_start = y_0.min(y_1).min(y_2);
-    if col_start >= screen.width() || row_start >= screen.height() {
+    if col_start >= width || row_start >= height {
         return;
     }
 
     let mut col_end = x_0.max(x_1).max(x_2);
-    if col_end >= screen.width() {
-        col_end = screen.width() - 1;
+    if col_end >= width {
+        col_end = width - 1;
     }
 
     let mut row_end = y_0.max(y_1).max(y_2);
-    if row_end >= screen.height() {
-        row_end = screen.height() - 1;
+    if row_end >= height {
+        row_end = height - 1;
     }
 
-    for row in row_start..row_end {
+    let band_end = first_row + frame.len() / (width * 4);
+
+    for row in row_start.max(first_row)..row_end.min(band_end) {
         for col in col_start..col_end {
             let (l1, l2, l3) = barycentric(
                 col as f64, row as f64, x_0 as f64, y_0 as f64, x_1 as f64, y_1 as f64, x_2 as f64,
                 y_2 as f64,
             );
 
-            if l1 < 0.0 || l1 > 1.0 {
+            if !(0.0..=1.0).contains(&l1) {
                 continue;
             }
-            if l2 < 0.0 || l2 > 1.0 {
+            if !(0.0..=1.0).contains(&l2) {
                 continue;
             }
-            if l3 < 0.0 || l3 > 1.0 {
+            if !(0.0..=1.0).contains(&l3) {
                 continue;
             }
 
-            set_pixel(screen, col, row, color);
+            let index = ((row - first_row) * width + col) * 4;
+            frame[index..index + 4].copy_from_slice(color);
         }
     }
 }
 
-pub fn render_terrain(screen: &mut Screen, terrain: &Terrain, color: &[u8; 4]) {
-    let square_size = (screen.height() / (terrain.height)) as f64;
+/// Screen space triangles of every marching squares cell, as `[x_0, y_0, x_1, y_1, x_2, y_2]`
+pub fn terrain_triangles(terrain: &Terrain, viewport: &Viewport) -> Vec<[usize; 6]> {
+    match terrain.contour_mode {
+        ContourMode::MarchingSquares => cell_triangles(terrain, viewport, &terrain.index_grid),
+        ContourMode::DualContouring => dual_triangles(terrain, viewport),
+    }
+}
+
+fn dual_triangles(terrain: &Terrain, viewport: &Viewport) -> Vec<[usize; 6]> {
+    dual_to_screen(terrain.dual_triangles(), viewport)
+}
+
+fn dual_to_screen(world_triangles: Vec<[f64; 6]>, viewport: &Viewport) -> Vec<[usize; 6]> {
+    let mut triangles = Vec::new();
+
+    for triangle in world_triangles {
+        let mut corners = [(0.0, 0.0); 3];
+        for (corner, point) in corners.iter_mut().zip(triangle.chunks(2)) {
+            *corner = viewport.sample_to_pixel(point[1], point[0]);
+        }
+
+        clip_to_screen(corners, &mut triangles);
+    }
+
+    triangles
+}
+
+/// Cut a triangle in signed pixel coordinates down to the part right of and below the
+/// top left screen corner, which is what the unsigned rasterizer can draw. The rest is
+/// fanned out into `triangles`.
+fn clip_to_screen(corners: [(f64, f64); 3], triangles: &mut Vec<[usize; 6]>) {
+    let pixel = |point: &(f64, f64)| (point.0.round() as usize, point.1.round() as usize);
+
+    if corners.iter().all(|&(x, y)| x >= 0.0 && y >= 0.0) {
+        let [(x_0, y_0), (x_1, y_1), (x_2, y_2)] = corners.map(|corner| pixel(&corner));
+        triangles.push([x_0, y_0, x_1, y_1, x_2, y_2]);
+        return;
+    }
+
+    let mut polygon = corners.to_vec();
+
+    // Sutherland-Hodgman against x >= 0, then y >= 0
+    for axis in [0, 1] {
+        let coordinate = |point: &(f64, f64)| if axis == 0 { point.0 } else { point.1 };
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+
+        for (i, start) in polygon.iter().enumerate() {
+            let end = &polygon[(i + 1) % polygon.len()];
+            let (start_value, end_value) = (coordinate(start), coordinate(end));
+
+            if start_value >= 0.0 {
+                clipped.push(*start);
+            }
+            if (start_value < 0.0) != (end_value < 0.0) {
+                let t = start_value / (start_value - end_value);
+                clipped.push((
+                    start.0 + (end.0 - start.0) * t,
+                    start.1 + (end.1 - start.1) * t,
+                ));
+            }
+        }
+
+        polygon = clipped;
+        if polygon.len() < 3 {
+            return;
+        }
+    }
+
+    let (x_0, y_0) = pixel(&polygon[0]);
+    for pair in polygon[1..].windows(2) {
+        let ((x_1, y_1), (x_2, y_2)) = (pixel(&pair[0]), pixel(&pair[1]));
+        triangles.push([x_0, y_0, x_1, y_1, x_2, y_2]);
+    }
+}
+
+/// Screen space triangles of one material, traced from its own index grid or, in dual
+/// mode, the part of the dual rock around its samples
+pub fn material_triangles(terrain: &Terrain, viewport: &Viewport, material: u8) -> Vec<[usize; 6]> {
+    match terrain.contour_mode {
+        ContourMode::MarchingSquares => cell_triangles(
+            terrain,
+            viewport,
+            &terrain.material_index_grids[material as usize],
+        ),
+        ContourMode::DualContouring => {
+            dual_to_screen(terrain.dual_material_triangles(material), viewport)
+        }
+    }
+}
 
-    for row in 0..terrain.height - 1 {
-        for col in 0..terrain.width - 1 {
-            let index = terrain.index_grid[row][col] as usize;
-            let triangle_list = &terrain.cell_edges[index];
+fn cell_triangles(
+    terrain: &Terrain,
+    viewport: &Viewport,
+    index_grid: &IndexField,
+) -> Vec<[usize; 6]> {
+    let square_size = viewport.square_size;
+    let mut triangles = Vec::new();
 
-            let offset_x = (col as f64 + 0.5) * square_size;
-            let offset_y = (row as f64 + 0.5) * square_size;
+    for (row, indices) in index_grid.iter().enumerate() {
+        for (col, &index) in indices.iter().enumerate() {
+            let triangle_list = &terrain.cell_edges[index as usize];
+
+            // Marching squares cells span between the centers of the sample squares
+            let (offset_x, offset_y) = viewport.sample_to_pixel(row as f64 + 0.5, col as f64 + 0.5);
 
             for triangle in triangle_list.chunks(6) {
-                let x_0 = (offset_x + triangle[0] * square_size as f64).round() as usize;
-                let y_0 = (offset_y + triangle[1] * square_size as f64).round() as usize;
-                let x_1 = (offset_x + triangle[2] * square_size as f64).round() as usize;
-                let y_1 = (offset_y + triangle[3] * square_size as f64).round() as usize;
-                let x_2 = (offset_x + triangle[4] * square_size as f64).round() as usize;
-                let y_2 = (offset_y + triangle[5] * square_size as f64).round() as usize;
-
-                render_triangle(screen, x_0, y_0, x_1, y_1, x_2, y_2, color);
+                let corner =
+                    |x: f64, y: f64| (offset_x + x * square_size, offset_y + y * square_size);
+                let corners = [
+                    corner(triangle[0], triangle[1]),
+                    corner(triangle[2], triangle[3]),
+                    corner(triangle[4], triangle[5]),
+                ];
+
+                clip_to_screen(corners, &mut triangles);
             }
         }
     }
+
+    triangles
+}
+
+pub fn render_triangles(screen: &mut Screen, triangles: &[[usize; 6]], color: &[u8; 4]) {
+    let (width, height) = (screen.width(), screen.height());
+
+    for triangle in triangles {
+        fill_triangle(screen.frame_mut(), width, height, 0, *triangle, color);
+    }
+}
+
+pub fn render_terrain(screen: &mut Screen, terrain: &Terrain, color: &[u8; 4]) {
+    let viewport = Viewport::fit(screen, terrain);
+    render_terrain_in_viewport(screen, terrain, &viewport, color);
+}
+
+pub fn render_terrain_in_viewport(
+    screen: &mut Screen,
+    terrain: &Terrain,
+    viewport: &Viewport,
+    color: &[u8; 4],
+) {
+    // The whole rock first, cells where three materials meet or with two materials on
+    // opposite corners leave their middle to this fill
+    let triangles = terrain_triangles(terrain, viewport);
+    draw_triangles(screen, &triangles, color);
+
+    // Stone is drawn in the rock color passed in, every other material in its own
+    for (material, Material { color, .. }) in MATERIALS.iter().enumerate().skip(1) {
+        let triangles = material_triangles(terrain, viewport, material as u8);
+        draw_triangles(screen, &triangles, color);
+    }
+}
+
+fn draw_triangles(screen: &mut Screen, triangles: &[[usize; 6]], color: &[u8; 4]) {
+    #[cfg(feature = "parallel")]
+    parallel::render_triangles(screen, triangles, color);
+    #[cfg(not(feature = "parallel"))]
+    render_triangles(screen, triangles, color);
+}
+
+/// Render every loaded chunk with the camera, given in world samples, at the center of the screen
+pub fn render_chunked_terrain(
+    screen: &mut Screen,
+    world: &ChunkedTerrain,
+    camera_row: f64,
+    camera_col: f64,
+    square_size: f64,
+    color: &[u8; 4],
+) {
+    let viewport = Viewport::centered_on(screen, camera_row, camera_col, square_size);
+
+    for (&coord, chunk) in world.chunks() {
+        let (origin_row, origin_col) = world.chunk_origin(coord);
+        let (offset_x, offset_y) = viewport.sample_to_pixel(origin_row as f64, origin_col as f64);
+
+        let chunk_viewport = Viewport {
+            offset_x,
+            offset_y,
+            square_size,
+        };
+
+        render_terrain_in_viewport(screen, chunk, &chunk_viewport, color);
+    }
 }
 
 pub fn render_terrain_grid(screen: &mut Screen, terrain: &Terrain, color: &[u8; 4]) {
-    let mut square_size = screen.height() / (terrain.height - 1);
-    if screen.width() < screen.height() {
-        square_size = screen.width() / (terrain.width - 1);
+    let viewport = Viewport::fit(screen, terrain);
+
+    // Clamp to the screen, rounding can push the far edge one pixel past it
+    let (x_start, y_start) = viewport.sample_to_pixel(0.0, 0.0);
+    let (x_end, y_end) = viewport.sample_to_pixel(terrain.height as f64, terrain.width as f64);
+    let x_start = x_start.round() as usize;
+    let y_start = y_start.round() as usize;
+    let x_end = (x_end.round() as usize).min(screen.width() - 1);
+    let y_end = (y_end.round() as usize).min(screen.height() - 1);
+
+    // Draw rows
+    for row in 0..=terrain.height {
+        let (_, y) = viewport.sample_to_pixel(row as f64, 0.0);
+        let y = (y.round() as usize).min(y_end);
+
+        set_row(screen, y, x_start, x_end, color);
     }
 
-    render_grid(screen, square_size, color);
+    // Draw coloumns
+    for col in 0..=terrain.width {
+        let (x, _) = viewport.sample_to_pixel(0.0, col as f64);
+        let x = (x.round() as usize).min(x_end);
+
+        set_col(screen, x, y_start, y_end, color);
+    }
 }
 
 pub fn render_character(
@@ -211,9 +713,10 @@ pub fn render_character(
     let symbol = get_char_symbol(c);
     let scaled_symbol = scale_symbol(symbol, scale);
 
-    for row in 0..scaled_symbol.len() {
-        for col in 0..scaled_symbol[0].len() {
-            if scaled_symbol[row][col] > 0 {
+    // Text running off a small window is cut off at its edges
+    for (row, pixels) in scaled_symbol.iter().enumerate() {
+        for (col, &pixel) in pixels.iter().enumerate() {
+            if pixel > 0 && x + col < screen.width() && y + row < screen.height() {
                 set_pixel(screen, x + col, y + row, color);
             }
         }
@@ -252,3 +755,27 @@ pub fn render_string(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rock_hanging_off_the_top_left_is_clipped_not_dropped() {
+        let terrain = Terrain::from_scalar_field(vec![vec![1.0; 4]; 4]);
+        let mut screen = Screen::headless(40, 40);
+
+        // Cells span 25 pixels, the first one starts 10 pixels above and left of the screen
+        let viewport = Viewport {
+            offset_x: -22.5,
+            offset_y: -22.5,
+            square_size: 25.0,
+        };
+        render_terrain_in_viewport(&mut screen, &terrain, &viewport, &[255, 0, 0, 255]);
+
+        let pixel = |x: usize, y: usize| &screen.frame()[(y * 40 + x) * 4..][..4];
+        for (x, y) in [(0, 0), (5, 0), (0, 5), (5, 5), (20, 3)] {
+            assert_eq!(pixel(x, y), [255, 0, 0, 255], "({}, {})", x, y);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use log::debug;
+
+use crate::terrain::Terrain;
+
+/// Change of the scalar field per world unit of distance from the surface of a stamped
+/// shape, the threshold itself sits on the surface
+const SCALAR_PER_UNIT: f64 = 0.5;
+
+/// Shapes defined by their signed distance in world units, negative inside
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Circle {
+        center: (f64, f64),
+        radius: f64,
+    },
+    /// Line segment from `start` to `end` with rounded ends
+    Capsule {
+        start: (f64, f64),
+        end: (f64, f64),
+        radius: f64,
+    },
+    /// Rectangle rotated by `angle` radians around its center
+    Box {
+        center: (f64, f64),
+        half_size: (f64, f64),
+        angle: f64,
+    },
+    /// Closed polygon through the given points, in any winding order
+    Polygon {
+        points: Vec<(f64, f64)>,
+    },
+}
+
+impl Shape {
+    pub fn distance(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Shape::Circle { center, radius } => length(x - center.0, y - center.1) - radius,
+            Shape::Capsule { start, end, radius } => {
+                segment_distance((x, y), *start, *end) - radius
+            }
+            Shape::Box {
+                center,
+                half_size,
+                angle,
+            } => {
+                // Rotate the point into the box's frame
+                let (sin, cos) = angle.sin_cos();
+                let (dx, dy) = (x - center.0, y - center.1);
+                let local_x = (dx * cos + dy * sin).abs() - half_size.0;
+                let local_y = (-dx * sin + dy * cos).abs() - half_size.1;
+
+                length(local_x.max(0.0), local_y.max(0.0)) + local_x.max(local_y).min(0.0)
+            }
+            Shape::Polygon { points } => polygon_distance((x, y), points),
+        }
+    }
+
+    /// `(min_x, min_y, max_x, max_y)` containing every point inside the shape
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        match self {
+            Shape::Circle { center, radius } => (
+                center.0 - radius,
+                center.1 - radius,
+                center.0 + radius,
+                center.1 + radius,
+            ),
+            Shape::Capsule { start, end, radius } => (
+                start.0.min(end.0) - radius,
+                start.1.min(end.1) - radius,
+                start.0.max(end.0) + radius,
+                start.1.max(end.1) + radius,
+            ),
+            Shape::Box {
+                center, half_size, ..
+            } => {
+                let reach = length(half_size.0, half_size.1);
+                (
+                    center.0 - reach,
+                    center.1 - reach,
+                    center.0 + reach,
+                    center.1 + reach,
+                )
+            }
+            Shape::Polygon { points } => points.iter().fold(
+                (
+                    f64::INFINITY,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                    f64::NEG_INFINITY,
+                ),
+                |(min_x, min_y, max_x, max_y), &(x, y)| {
+                    (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                },
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Fill the shape with rock
+    Union,
+    /// Carve the shape out of the rock
+    Subtraction,
+    /// Keep only the rock inside the shape
+    Intersection,
+}
+
+impl Terrain {
+    /// Combine a shape with the rock of the terrain.
+    ///
+    /// `smoothness` is the distance in world units over which the shape blends into the
+    /// existing contour with a smooth minimum, 0 gives a sharp crease. Only samples the
+    /// shape can influence are touched, except for intersections which clear everything
+    /// outside the shape.
+    pub fn stamp(&mut self, shape: &Shape, operation: Operation, smoothness: f64) {
+        debug!("stamping {:?} with {:?}", shape, operation);
+
+        let (row_start, col_start, row_end, col_end) = match operation {
+            Operation::Intersection => (0, 0, self.height - 1, self.width - 1),
+            Operation::Union | Operation::Subtraction => {
+                // Beyond this margin the blend no longer changes the field
+                let margin = smoothness + 1.0 / SCALAR_PER_UNIT;
+                let (min_x, min_y, max_x, max_y) = shape.bounds();
+
+                let (row_start, col_start) = self.clamped_sample(min_x - margin, min_y - margin);
+                let (row_end, col_end) = self.clamped_sample(max_x + margin, max_y + margin);
+
+                (row_start, col_start, row_end, col_end)
+            }
+        };
+
+        for row in row_start..=row_end {
+            for col in col_start..=col_end {
+                let (x, y) = self.sample_to_world(row, col);
+
+                // Treat the scalar field as a distance field around the threshold
+                let terrain_distance =
+                    (self.threshold() - self.scalar_field[row][col]) / SCALAR_PER_UNIT;
+                let shape_distance = shape.distance(x, y);
+
+                let distance = match operation {
+                    Operation::Union => smooth_min(terrain_distance, shape_distance, smoothness),
+                    Operation::Subtraction => {
+                        smooth_max(terrain_distance, -shape_distance, smoothness)
+                    }
+                    Operation::Intersection => {
+                        smooth_max(terrain_distance, shape_distance, smoothness)
+                    }
+                };
+
+                self.scalar_field[row][col] = self.distance_to_scalar(distance);
+            }
+        }
+
+        self.construct_index_grid();
+    }
+
+    /// Scalar of a sample `distance` world units from the surface of the rock, positive
+    /// in the cave and negative inside the rock, the way `stamp` rasterizes shapes
+    pub(crate) fn distance_to_scalar(&self, distance: f64) -> f64 {
+        (self.threshold() - distance * SCALAR_PER_UNIT).clamp(0.0, 1.0)
+    }
+
+    /// `(row, col)` of the sample under a world position, clamped onto the terrain
+    fn clamped_sample(&self, x: f64, y: f64) -> (usize, usize) {
+        let col = x.floor().clamp(0.0, (self.width - 1) as f64) as usize;
+        let row = y.floor().clamp(0.0, (self.height - 1) as f64) as usize;
+
+        (row, col)
+    }
+}
+
+/// Polynomial smooth minimum, equal to `min` when `k` is 0 or the values are more than `k` apart
+pub fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+
+pub fn smooth_max(a: f64, b: f64, k: f64) -> f64 {
+    -smooth_min(-a, -b, k)
+}
+
+/// Euclidean distance between two points
+pub(crate) fn point_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    length(a.0 - b.0, a.1 - b.1)
+}
+
+fn length(x: f64, y: f64) -> f64 {
+    (x * x + y * y).sqrt()
+}
+
+fn segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = dx * dx + dy * dy;
+
+    let t = if length_squared > 0.0 {
+        (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    length(point.0 - start.0 - t * dx, point.1 - start.1 - t * dy)
+}
+
+fn polygon_distance(point: (f64, f64), points: &[(f64, f64)]) -> f64 {
+    if points.is_empty() {
+        return f64::INFINITY;
+    }
+
+    let mut distance = f64::INFINITY;
+    let mut inside = false;
+
+    for (index, &start) in points.iter().enumerate() {
+        let end = points[(index + 1) % points.len()];
+        distance = distance.min(segment_distance(point, start, end));
+
+        // Even-odd rule, count the edges crossed by a ray towards positive x
+        if (start.1 > point.1) != (end.1 > point.1) {
+            let crossing_x = start.0 + (point.1 - start.1) / (end.1 - start.1) * (end.0 - start.0);
+            if point.0 < crossing_x {
+                inside = !inside;
+            }
+        }
+    }
+
+    if inside {
+        -distance
+    } else {
+        distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circle() -> Shape {
+        Shape::Circle {
+            center: (10.0, 10.0),
+            radius: 4.0,
+        }
+    }
+
+    fn filled(scalar: f64) -> Terrain {
+        Terrain::from_scalar_field(vec![vec![scalar; 20]; 20])
+    }
+
+    #[test]
+    fn shapes_are_negative_inside() {
+        let shapes = [
+            circle(),
+            Shape::Capsule {
+                start: (6.0, 10.0),
+                end: (14.0, 10.0),
+                radius: 2.0,
+            },
+            Shape::Box {
+                center: (10.0, 10.0),
+                half_size: (3.0, 2.0),
+                angle: 0.5,
+            },
+            Shape::Polygon {
+                points: vec![(6.0, 6.0), (14.0, 8.0), (10.0, 14.0)],
+            },
+        ];
+
+        for shape in &shapes {
+            assert!(shape.distance(10.0, 10.0) < 0.0, "{:?}", shape);
+            assert!(shape.distance(0.0, 0.0) > 0.0, "{:?}", shape);
+
+            let (min_x, min_y, max_x, max_y) = shape.bounds();
+            assert!(min_x <= 10.0 && 10.0 <= max_x && min_y <= 10.0 && 10.0 <= max_y);
+        }
+        assert!((circle().distance(10.0, 16.0) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn union_fills_the_shape() {
+        let mut terrain = filled(0.0);
+
+        terrain.stamp(&circle(), Operation::Union, 0.0);
+
+        assert!(terrain.is_solid(10.0, 10.0));
+        assert!(!terrain.is_solid(10.0, 15.0));
+        assert_eq!(terrain.scalar_field[0][0], 0.0);
+    }
+
+    #[test]
+    fn sharp_restamping_does_not_grow() {
+        let mut terrain = filled(0.0);
+        terrain.stamp(&circle(), Operation::Union, 1.0);
+
+        terrain.stamp(&circle(), Operation::Union, 0.0);
+        let once = terrain.scalar_field.clone();
+        terrain.stamp(&circle(), Operation::Union, 0.0);
+
+        assert_eq!(terrain.scalar_field, once);
+    }
+
+    #[test]
+    fn subtraction_carves_the_shape() {
+        let mut terrain = filled(1.0);
+
+        terrain.stamp(&circle(), Operation::Subtraction, 0.0);
+
+        assert!(!terrain.is_solid(10.0, 10.0));
+        assert!(terrain.is_solid(10.0, 15.0));
+        assert_eq!(terrain.scalar_field[0][0], 1.0);
+    }
+
+    #[test]
+    fn intersection_keeps_only_the_shape() {
+        let mut terrain = filled(1.0);
+
+        terrain.stamp(&circle(), Operation::Intersection, 0.0);
+
+        assert!(terrain.is_solid(10.0, 10.0));
+        assert!(!terrain.is_solid(10.0, 15.0));
+        assert!(terrain.scalar_field[0][0] <= terrain.threshold());
+    }
+
+    #[test]
+    fn smooth_min_stays_within_a_quarter_of_k() {
+        for (a, b) in [(0.0, 0.0), (1.0, 1.5), (-2.0, -1.2), (3.0, 0.5), (0.0, 5.0)] {
+            for k in [0.0, 0.5, 1.0, 2.0] {
+                let smooth = smooth_min(a, b, k);
+
+                assert!(smooth <= a.min(b));
+                assert!(smooth >= a.min(b) - k / 4.0);
+                if (a - b).abs() >= k {
+                    assert_eq!(smooth, a.min(b));
+                }
+                assert_eq!(smooth_max(a, b, k), -smooth_min(-a, -b, k));
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::terrain::{ContourMode, Terrain};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SurfaceKind {
+    /// Faces up, something can stand on it
+    Floor,
+    Wall,
+    /// Faces down, things hang from it
+    Ceiling,
+}
+
+/// A contour segment with the direction it faces
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Surface {
+    /// `[x_0, y_0, x_1, y_1]` in world space
+    pub segment: [f64; 4],
+    /// Unit normal pointing out of the rock into the cave, y grows downwards
+    pub normal: (f64, f64),
+    /// Angle of the segment against the horizontal in radians, 0 for flat and pi/2 for sheer
+    pub slope: f64,
+    pub kind: SurfaceKind,
+}
+
+impl Surface {
+    pub fn midpoint(&self) -> (f64, f64) {
+        (
+            (self.segment[0] + self.segment[2]) / 2.0,
+            (self.segment[1] + self.segment[3]) / 2.0,
+        )
+    }
+}
+
+impl Terrain {
+    /// Every contour segment classified by the direction it faces. Marching squares
+    /// segments are interpolated between the samples, so they lie on the contour
+    /// `is_solid` and `raycast` test against. Dual segments are traced as drawn.
+    ///
+    /// Segments whose normal is within `max_floor_angle` radians of straight up are
+    /// floors, within `max_ceiling_angle` of straight down are ceilings, and the rest
+    /// are walls.
+    pub fn surfaces(
+        &self,
+        max_floor_angle: f64,
+        max_ceiling_angle: f64,
+    ) -> impl Iterator<Item = Surface> + '_ {
+        let segments = match self.contour_mode {
+            ContourMode::MarchingSquares => self.interpolated_contour_segments(),
+            ContourMode::DualContouring => self.dual_contour_segments(),
+        };
+
+        segments.into_iter().map(move |segment| {
+            let [x_0, y_0, x_1, y_1] = segment;
+            let length = ((x_1 - x_0).powi(2) + (y_1 - y_0).powi(2)).sqrt();
+
+            // Segments keep the rock on their right, so the left hand side faces the cave
+            let normal = ((y_1 - y_0) / length, (x_0 - x_1) / length);
+
+            // Angle between the normal and straight up, which is negative y
+            let angle_from_up = (-normal.1).clamp(-1.0, 1.0).acos();
+            let angle_from_down = std::f64::consts::PI - angle_from_up;
+
+            let kind = if angle_from_up <= max_floor_angle {
+                SurfaceKind::Floor
+            } else if angle_from_down <= max_ceiling_angle {
+                SurfaceKind::Ceiling
+            } else {
+                SurfaceKind::Wall
+            };
+
+            Surface {
+                segment,
+                normal,
+                slope: angle_from_up.min(angle_from_down),
+                kind,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use super::*;
+
+    /// Open room from samples 5 to 14 down and 3 to 26 across, with a shelf of rock on
+    /// rows 9 and 10 sticking out of the left wall up to column 11
+    fn room_with_shelf() -> Terrain {
+        let mut scalar_field = vec![vec![1.0; 30]; 20];
+        for (row, scalars) in scalar_field.iter_mut().enumerate().take(15).skip(5) {
+            for (col, scalar) in scalars.iter_mut().enumerate().take(27).skip(3) {
+                let shelf = (9..=10).contains(&row) && col <= 11;
+                *scalar = if shelf { 1.0 } else { 0.0 };
+            }
+        }
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    /// The surface with its midpoint at `(x, y)`. Walls cross the threshold a third of
+    /// the way from the cave sample to the rock, so they sit at .17 or .83.
+    fn surface_at(terrain: &Terrain, x: f64, y: f64) -> Surface {
+        terrain
+            .surfaces(FRAC_PI_4, FRAC_PI_4)
+            .find(|surface| {
+                let (mid_x, mid_y) = surface.midpoint();
+                (mid_x - x).abs() < 1e-9 && (mid_y - y).abs() < 1e-9
+            })
+            .unwrap_or_else(|| panic!("no surface at ({}, {})", x, y))
+    }
+
+    fn assert_surface(surface: Surface, kind: SurfaceKind, normal: (f64, f64), slope: f64) {
+        assert_eq!(surface.kind, kind);
+        assert!((surface.normal.0 - normal.0).abs() < 1e-9, "{:?}", surface);
+        assert!((surface.normal.1 - normal.1).abs() < 1e-9, "{:?}", surface);
+        assert!((surface.slope - slope).abs() < 1e-9, "{:?}", surface);
+    }
+
+    #[test]
+    fn flat_floors_and_ceilings() {
+        let terrain = room_with_shelf();
+
+        let floor = surface_at(&terrain, 20.0, 14.83);
+        assert_surface(floor, SurfaceKind::Floor, (0.0, -1.0), 0.0);
+
+        let ceiling = surface_at(&terrain, 20.0, 5.17);
+        assert_surface(ceiling, SurfaceKind::Ceiling, (0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn sheer_walls() {
+        let terrain = room_with_shelf();
+
+        let left = surface_at(&terrain, 3.17, 7.0);
+        assert_surface(left, SurfaceKind::Wall, (1.0, 0.0), FRAC_PI_2);
+
+        let right = surface_at(&terrain, 26.83, 7.0);
+        assert_surface(right, SurfaceKind::Wall, (-1.0, 0.0), FRAC_PI_2);
+    }
+
+    #[test]
+    fn overhang_is_a_ceiling_under_a_floor() {
+        let terrain = room_with_shelf();
+
+        let top = surface_at(&terrain, 7.0, 8.83);
+        assert_surface(top, SurfaceKind::Floor, (0.0, -1.0), 0.0);
+
+        let underside = surface_at(&terrain, 7.0, 11.17);
+        assert_surface(underside, SurfaceKind::Ceiling, (0.0, 1.0), 0.0);
+
+        let tip = surface_at(&terrain, 12.17, 10.0);
+        assert_surface(tip, SurfaceKind::Wall, (1.0, 0.0), FRAC_PI_2);
+    }
+
+    #[test]
+    fn angles_decide_the_kind() {
+        let terrain = room_with_shelf();
+
+        // Everything is a wall when no angle is allowed, and nothing is with every angle
+        assert!(terrain
+            .surfaces(-1.0, -1.0)
+            .all(|surface| surface.kind == SurfaceKind::Wall));
+        assert!(terrain
+            .surfaces(FRAC_PI_2, FRAC_PI_2)
+            .all(|surface| surface.kind != SurfaceKind::Wall));
+
+        for surface in terrain.surfaces(FRAC_PI_4, FRAC_PI_4) {
+            let length = (surface.normal.0.powi(2) + surface.normal.1.powi(2)).sqrt();
+            assert!((length - 1.0).abs() < 1e-9);
+
+            let facing = match surface.kind {
+                SurfaceKind::Floor => -surface.normal.1,
+                SurfaceKind::Ceiling => surface.normal.1,
+                SurfaceKind::Wall => FRAC_PI_4.cos() - surface.normal.1.abs(),
+            };
+            assert!(facing > 0.0, "{:?}", surface);
+        }
+    }
+}
@@ -1,50 +1,118 @@
 use log::debug;
-use noise::{NoiseFn, Perlin};
 
-type ScalarField = Vec<Vec<f64>>;
-type IndexField = Vec<Vec<u8>>;
+#[cfg(feature = "parallel")]
+use crate::parallel;
+use crate::{
+    material::{MATERIALS, STONE},
+    modifier::{NoiseModifier, TerrainNoise},
+    region::{RegionKind, RegionMap},
+};
+
+pub type ScalarField = Vec<Vec<f64>>;
+pub type IndexField = Vec<Vec<u8>>;
+/// Material ID of every sample, indexing `material::MATERIALS`
+pub type MaterialField = Vec<Vec<u8>>;
+
+/// Geometry used to draw the rock.
+///
+/// Rendering and `surfaces` follow the mode. `is_solid`, collisions and raycasts, the
+/// distance field, contour segments and the export always use marching squares, so in
+/// dual mode they can be off the drawn rock by up to half a sample around corners.
+/// `is_solid`, collisions and raycasts interpolate the contour between the samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContourMode {
+    /// The `cell_edges` table, corners are cut off at the edge midpoints
+    #[default]
+    MarchingSquares,
+    /// One vertex per cell fitted to the gradient, keeps sharp corners. See `dual`.
+    DualContouring,
+}
+
+/// How the ambiguous cases 5 and 10, where rock only touches diagonally across a
+/// cell, are drawn.
+///
+/// When the rock is joined the index grid holds `JOINED_SADDLE_5` or `JOINED_SADDLE_10`
+/// instead of 5 or 10, which index the joined shapes in `cell_edges` and `contour_edges`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaddlePolicy {
+    /// Always keep the rock apart, leaving a passage through the cell
+    Split,
+    /// Always join the rock across the cell
+    Join,
+    /// Join when the average of the four corners is above the threshold
+    CenterAverage,
+    /// Join when the saddle point of the bilinear interpolation between the corners is
+    /// above the threshold, which matches the topology of the interpolated field
+    #[default]
+    AsymptoticDecider,
+}
+
+pub const JOINED_SADDLE_5: u8 = 16;
+pub const JOINED_SADDLE_10: u8 = 17;
 
 pub struct Terrain {
     pub width: usize,
     pub height: usize,
     pub scalar_field: ScalarField,
+    pub materials: MaterialField,
     threshold: f64,
     pub index_grid: IndexField,
+    /// Marching squares cases of every material on its own, indexed by material ID.
+    /// A corner is set when its sample is solid and made of that material, so borders
+    /// between materials inside the rock are traced as well as the cave walls.
+    pub material_index_grids: Vec<IndexField>,
     pub cell_edges: Vec<Vec<f64>>,
+    pub contour_edges: Vec<Vec<f64>>,
+    pub contour_mode: ContourMode,
+    /// Rebuild the index grid after changing this
+    pub saddle_policy: SaddlePolicy,
 }
 
 impl Terrain {
     pub fn new(width: usize, height: usize) -> Self {
+        Self::with_origin(width, height, 0, 0)
+    }
+
+    /// Create terrain whose first sample sits at `(origin_row, origin_col)` in world space.
+    ///
+    /// Noise is keyed by world coordinates, so terrains sharing a border sample the same values there.
+    pub fn with_origin(width: usize, height: usize, origin_row: i64, origin_col: i64) -> Self {
+        Self::with_modifiers(width, height, origin_row, origin_col, &[])
+    }
+
+    /// Create terrain like `with_origin`, running the noise through a stack of modifiers
+    /// before it is thresholded. See `NoiseModifier`.
+    pub fn with_modifiers(
+        width: usize,
+        height: usize,
+        origin_row: i64,
+        origin_col: i64,
+        modifiers: &[NoiseModifier],
+    ) -> Self {
         debug!("creating terrain");
-        debug!("width: {} height: {}", width, height);
+        debug!(
+            "width: {} height: {} origin: ({}, {})",
+            width, height, origin_row, origin_col
+        );
+        debug!("modifiers: {:?}", modifiers);
 
-        let mut scalar_field = Vec::with_capacity(height);
-        for _ in 0..height {
-            let mut row = Vec::<f64>::with_capacity(width);
-            for _ in 0..width {
-                row.push(0.0);
-            }
-            scalar_field.push(row);
-        }
+        #[cfg(feature = "parallel")]
+        let scalar_field = parallel::sample_noise(width, height, origin_row, origin_col, modifiers);
+        #[cfg(not(feature = "parallel"))]
+        let scalar_field = sample_noise(width, height, origin_row, origin_col, modifiers);
 
-        let perlin = Perlin::new(1);
+        Self::from_scalar_field(scalar_field)
+    }
 
-        for row in 0..height {
-            for col in 0..width {
-                scalar_field[row][col] =
-                    perlin.get([col as f64 + 0.5, row as f64 + 0.5]) * 0.5 + 0.5;
-            }
-        }
+    /// Create terrain from an existing field, such as a slice of a `Volume`
+    pub fn from_scalar_field(scalar_field: ScalarField) -> Self {
+        let height = scalar_field.len();
+        let width = scalar_field[0].len();
         debug!("scalar_field: {:?}", scalar_field);
 
-        let mut index_grid = Vec::with_capacity(height - 1);
-        for _ in 0..height - 1 {
-            let mut row = Vec::<u8>::with_capacity(width - 1);
-            for _ in 0..width - 1 {
-                row.push(0);
-            }
-            index_grid.push(row);
-        }
+        let materials = vec![vec![STONE; width]; height];
+        let index_grid = vec![vec![0; width - 1]; height - 1];
+        let material_index_grids = vec![index_grid.clone(); MATERIALS.len()];
 
         let cell_edges = vec![
             vec![],
@@ -75,67 +143,492 @@ impl Terrain {
                 1.0, 1.0,
             ],
             vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0],
+            // Cases 5 and 10 with the diagonal rock joined across the cell, see `SaddlePolicy`
+            vec![
+                0.5, 0.0, 1.0, 0.0, 1.0, 0.5, 0.5, 0.0, 1.0, 0.5, 0.5, 1.0, 0.5, 0.0, 0.5, 1.0,
+                0.0, 1.0, 0.5, 0.0, 0.0, 1.0, 0.0, 0.5,
+            ],
+            vec![
+                0.0, 0.0, 0.5, 0.0, 1.0, 0.5, 0.0, 0.0, 1.0, 0.5, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0,
+                0.5, 1.0, 0.0, 0.0, 0.5, 1.0, 0.0, 0.5,
+            ],
+        ];
+
+        // Line segments between edge midpoints for every case, as x_0, y_0, x_1, y_1.
+        // Segments run with rock on their right in screen space, so (y_1 - y_0, x_0 - x_1)
+        // points out of the rock into the cave.
+        let contour_edges = vec![
+            vec![],
+            vec![0.0, 0.5, 0.5, 1.0],
+            vec![0.5, 1.0, 1.0, 0.5],
+            vec![0.0, 0.5, 1.0, 0.5],
+            vec![1.0, 0.5, 0.5, 0.0],
+            vec![0.0, 0.5, 0.5, 1.0, 1.0, 0.5, 0.5, 0.0],
+            vec![0.5, 1.0, 0.5, 0.0],
+            vec![0.0, 0.5, 0.5, 0.0],
+            vec![0.5, 0.0, 0.0, 0.5],
+            vec![0.5, 0.0, 0.5, 1.0],
+            vec![0.5, 0.0, 0.0, 0.5, 0.5, 1.0, 1.0, 0.5],
+            vec![0.5, 0.0, 1.0, 0.5],
+            vec![1.0, 0.5, 0.0, 0.5],
+            vec![1.0, 0.5, 0.5, 1.0],
+            vec![0.5, 1.0, 0.0, 0.5],
+            vec![],
+            vec![0.0, 0.5, 0.5, 0.0, 1.0, 0.5, 0.5, 1.0],
+            vec![0.5, 0.0, 1.0, 0.5, 0.5, 1.0, 0.0, 0.5],
         ];
 
         let threshold = 0.33;
-        debug_assert!(threshold >= 0.0 && threshold <= 1.0);
+        debug_assert!((0.0..=1.0).contains(&threshold));
 
         let mut new_terrain = Self {
             width,
             height,
             scalar_field,
+            materials,
             threshold,
             index_grid,
+            material_index_grids,
             cell_edges,
+            contour_edges,
+            contour_mode: ContourMode::default(),
+            saddle_policy: SaddlePolicy::default(),
         };
 
         Self::construct_index_grid(&mut new_terrain);
 
-        return new_terrain;
+        new_terrain
     }
 
     pub fn modify_scalar_field(&mut self, row: usize, col: usize, new_scalar: f64) {
-        debug_assert!(new_scalar >= 0.0 && new_scalar <= 1.0);
+        debug_assert!((0.0..=1.0).contains(&new_scalar));
 
         self.scalar_field[row][col] = new_scalar;
         self.construct_index_grid();
     }
 
-    fn construct_index_grid(&mut self) {
+    /// Fill empty pockets and erode solid islands smaller than the given number of samples.
+    ///
+    /// The largest region of each kind is always kept. Removed samples are mirrored
+    /// across the threshold rather than set to 0 or 1, so values near the old contour
+    /// stay near the threshold and the field keeps its slope instead of forming a step.
+    ///
+    /// Returns the number of pockets filled and islands eroded.
+    pub fn remove_small_regions(
+        &mut self,
+        min_pocket_size: usize,
+        min_island_size: usize,
+    ) -> (usize, usize) {
+        let region_map = RegionMap::new(self);
+
+        let largest_of_kind = |kind| {
+            region_map
+                .regions_of_kind(kind)
+                .max_by_key(|(_, region)| region.size)
+                .map(|(label, _)| label)
+        };
+        let largest_pocket = largest_of_kind(RegionKind::Empty);
+        let largest_island = largest_of_kind(RegionKind::Solid);
+
+        let removed: Vec<bool> = region_map
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(label, region)| match region.kind {
+                RegionKind::Empty => Some(label) != largest_pocket && region.size < min_pocket_size,
+                RegionKind::Solid => Some(label) != largest_island && region.size < min_island_size,
+            })
+            .collect();
+
+        for (row, labels) in region_map.labels.iter().enumerate() {
+            for (col, &label) in labels.iter().enumerate() {
+                if !removed[label] {
+                    continue;
+                }
+
+                let scalar = self.scalar_field[row][col];
+                let mirrored = 2.0 * self.threshold - scalar;
+
+                self.scalar_field[row][col] = match region_map.regions[label].kind {
+                    // Must end up strictly above the threshold to count as solid
+                    RegionKind::Empty => mirrored.max(self.threshold + f64::EPSILON).min(1.0),
+                    RegionKind::Solid => mirrored.min(self.threshold).max(0.0),
+                };
+            }
+        }
+
+        self.construct_index_grid();
+
+        let count_removed = |kind| {
+            region_map
+                .regions_of_kind(kind)
+                .filter(|&(label, _)| removed[label])
+                .count()
+        };
+        let counts = (
+            count_removed(RegionKind::Empty),
+            count_removed(RegionKind::Solid),
+        );
+        debug!("filled pockets: {} eroded islands: {}", counts.0, counts.1);
+
+        counts
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// World position `(x, y)` of the center of a sample.
+    ///
+    /// One world unit is the spacing between samples, with the terrain spanning
+    /// `(0, 0)` to `(width, height)` and each sample sitting in the middle of its square.
+    pub fn sample_to_world(&self, row: usize, col: usize) -> (f64, f64) {
+        (col as f64 + 0.5, row as f64 + 0.5)
+    }
+
+    /// `(row, col)` of the sample whose square contains a world position, if it is on the terrain
+    pub fn world_to_sample(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+
+        let (row, col) = (y.floor() as usize, x.floor() as usize);
+        if row >= self.height || col >= self.width {
+            return None;
+        }
+
+        Some((row, col))
+    }
+
+    /// Every marching squares contour segment as `[x_0, y_0, x_1, y_1]` in world space
+    pub fn contour_segments(&self) -> Vec<[f64; 4]> {
+        self.traced_segments(&self.index_grid)
+    }
+
+    /// Contour of one material in world space, including its borders with other materials
+    pub fn material_contour_segments(&self, material: u8) -> Vec<[f64; 4]> {
+        self.traced_segments(&self.material_index_grids[material as usize])
+    }
+
+    /// Every contour segment interpolated between the samples like
+    /// `interpolated_cell_segments`, leaving out ones that shrink to a point
+    pub fn interpolated_contour_segments(&self) -> Vec<[f64; 4]> {
+        let mut segments = Vec::new();
+
+        for row in 0..self.height.saturating_sub(1) {
+            for col in 0..self.width.saturating_sub(1) {
+                segments.extend(
+                    self.interpolated_cell_segments(row, col)
+                        .filter(|[x_0, y_0, x_1, y_1]| x_0 != x_1 || y_0 != y_1),
+                );
+            }
+        }
+
+        segments
+    }
+
+    /// Contour segments traced in the marching squares cell at `(row, col)`, in world coordinates
+    pub fn cell_segments(&self, row: usize, col: usize) -> impl Iterator<Item = [f64; 4]> + '_ {
+        self.traced_cell_segments(&self.index_grid, row, col)
+    }
+
+    /// Like `cell_segments`, with every end moved along its cell edge to where the
+    /// scalar field crosses the threshold instead of the middle of the edge
+    pub fn interpolated_cell_segments(
+        &self,
+        row: usize,
+        col: usize,
+    ) -> impl Iterator<Item = [f64; 4]> + '_ {
+        let (offset_x, offset_y) = self.sample_to_world(row, col);
+        let index = self.index_grid[row][col] as usize;
+
+        self.contour_edges[index].chunks(4).map(move |edge| {
+            let (x_0, y_0) = self.interpolate_cell_point(row, col, edge[0], edge[1]);
+            let (x_1, y_1) = self.interpolate_cell_point(row, col, edge[2], edge[3]);
+
+            [
+                offset_x + x_0,
+                offset_y + y_0,
+                offset_x + x_1,
+                offset_y + y_1,
+            ]
+        })
+    }
+
+    /// Move a cell edge midpoint, in cell coordinates, to where the scalar field crosses
+    /// the threshold along that edge. Corners and other points are left where they are.
+    fn interpolate_cell_point(&self, row: usize, col: usize, x: f64, y: f64) -> (f64, f64) {
+        // Where the threshold lies between two samples, from 0 at the first to 1 at the second
+        let crossing = |(row_0, col_0): (usize, usize), (row_1, col_1): (usize, usize)| {
+            let value_0 = self.scalar_field[row_0][col_0];
+            let value_1 = self.scalar_field[row_1][col_1];
+            ((self.threshold - value_0) / (value_1 - value_0)).clamp(0.0, 1.0)
+        };
+        let (top_left, top_right) = ((row, col), (row, col + 1));
+        let (bottom_left, bottom_right) = ((row + 1, col), (row + 1, col + 1));
+
+        match (x, y) {
+            (0.5, 0.0) => (crossing(top_left, top_right), y),
+            (0.5, 1.0) => (crossing(bottom_left, bottom_right), y),
+            (0.0, 0.5) => (x, crossing(top_left, bottom_left)),
+            (1.0, 0.5) => (x, crossing(top_right, bottom_right)),
+            _ => (x, y),
+        }
+    }
+
+    fn traced_segments(&self, index_grid: &IndexField) -> Vec<[f64; 4]> {
+        let mut segments = Vec::new();
+
+        for row in 0..index_grid.len() {
+            for col in 0..index_grid[row].len() {
+                segments.extend(self.traced_cell_segments(index_grid, row, col));
+            }
+        }
+
+        segments
+    }
+
+    fn traced_cell_segments(
+        &self,
+        index_grid: &IndexField,
+        row: usize,
+        col: usize,
+    ) -> impl Iterator<Item = [f64; 4]> + '_ {
+        // Cells span between sample centers
+        let (offset_x, offset_y) = self.sample_to_world(row, col);
+        let index = index_grid[row][col] as usize;
+
+        self.contour_edges[index].chunks(4).map(move |edge| {
+            [
+                offset_x + edge[0],
+                offset_y + edge[1],
+                offset_x + edge[2],
+                offset_y + edge[3],
+            ]
+        })
+    }
+
+    /// Whether a world position lies inside the marching squares rock, bounded by the same
+    /// interpolated contour as `interpolated_cell_segments`, `raycast` and `overlaps_circle`.
+    ///
+    /// Positions between the outer samples and the edge of the terrain take the
+    /// nearest cell, positions off the terrain are never solid.
+    pub fn is_solid(&self, x: f64, y: f64) -> bool {
+        if x < 0.0 || y < 0.0 || x > self.width as f64 || y > self.height as f64 {
+            return false;
+        }
+
+        // Position relative to the cell grid, which starts at the first sample center
+        let cell_x = (x - 0.5).clamp(0.0, (self.width - 1) as f64);
+        let cell_y = (y - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let col = (cell_x.floor() as usize).min(self.width - 2);
+        let row = (cell_y.floor() as usize).min(self.height - 2);
+        let (local_x, local_y) = (cell_x - col as f64, cell_y - row as f64);
+
+        let index = self.index_grid[row][col] as usize;
+        // Moving the midpoints along the cell edges keeps the rock convex, so the
+        // triangles still cover it without overlapping
+        self.cell_edges[index].chunks(6).any(|triangle| {
+            let mut corners = [0.0; 6];
+            for (corner, point) in corners.chunks_mut(2).zip(triangle.chunks(2)) {
+                (corner[0], corner[1]) = self.interpolate_cell_point(row, col, point[0], point[1]);
+            }
+            point_in_triangle(local_x, local_y, &corners)
+        })
+    }
+
+    /// Samples above the threshold as 1 (solid rock) and the rest as 0 (empty cave)
+    pub fn thresholded_field(&self) -> IndexField {
+        threshold_field(&self.scalar_field, self.threshold)
+    }
+
+    pub fn construct_index_grid(&mut self) {
+        #[cfg(feature = "parallel")]
+        let thresholded_field = parallel::threshold_field(&self.scalar_field, self.threshold);
+        #[cfg(not(feature = "parallel"))]
         let thresholded_field = threshold_field(&self.scalar_field, self.threshold);
         debug!("thresholded_field: {:?}", thresholded_field);
 
+        #[cfg(feature = "parallel")]
+        let index_grid = parallel::index_field(&thresholded_field);
+        #[cfg(not(feature = "parallel"))]
+        let index_grid = index_field(&thresholded_field);
+
+        self.index_grid = index_grid;
         for row in 0..self.height - 1 {
             for col in 0..self.width - 1 {
-                // Compose 4 bits at corners of each cell to build a binary index
-                // Start top left and rotate clockwise
-                // Build most significant bit to least significant bit
-                let mut index = 0;
-                index |= thresholded_field[row][col] << 3;
-                index |= thresholded_field[row][col + 1] << 2;
-                index |= thresholded_field[row + 1][col + 1] << 1;
-                index |= thresholded_field[row + 1][col] << 0;
-
-                self.index_grid[row][col] = index;
+                self.index_grid[row][col] =
+                    self.resolve_saddle(row, col, self.index_grid[row][col]);
             }
         }
         debug!("index_grid: {:?}", self.index_grid);
+
+        for material in 0..MATERIALS.len() {
+            #[cfg(feature = "parallel")]
+            let material_field = parallel::threshold_material_field(
+                &self.scalar_field,
+                &self.materials,
+                self.threshold,
+                material as u8,
+            );
+            #[cfg(not(feature = "parallel"))]
+            let material_field = threshold_material_field(
+                &self.scalar_field,
+                &self.materials,
+                self.threshold,
+                material as u8,
+            );
+
+            #[cfg(feature = "parallel")]
+            let mut material_index_grid = parallel::index_field(&material_field);
+            #[cfg(not(feature = "parallel"))]
+            let mut material_index_grid = index_field(&material_field);
+
+            // Saddles are decided the same way as in the index grid so the outline of a
+            // material joins and splits along with the rock
+            for (row, indices) in material_index_grid.iter_mut().enumerate() {
+                for (col, index) in indices.iter_mut().enumerate() {
+                    *index = self.resolve_saddle(row, col, *index);
+                }
+            }
+
+            self.material_index_grids[material] = material_index_grid;
+        }
     }
+
+    /// Rebuild the index grid for the cells in `row_start..=row_end` and `col_start..=col_end` only
+    pub fn update_index_grid(
+        &mut self,
+        row_start: usize,
+        col_start: usize,
+        row_end: usize,
+        col_end: usize,
+    ) {
+        // Threshold just the samples at the corners of the cells being rebuilt
+        let threshold_block = |is_set: &dyn Fn(usize, usize) -> bool| -> IndexField {
+            (row_start..=row_end + 1)
+                .map(|row| {
+                    (col_start..=col_end + 1)
+                        .map(|col| u8::from(is_set(row, col)))
+                        .collect()
+                })
+                .collect()
+        };
+
+        let thresholded_block =
+            threshold_block(&|row, col| self.scalar_field[row][col] > self.threshold);
+        let material_blocks: Vec<IndexField> = (0..MATERIALS.len())
+            .map(|material| {
+                threshold_block(&|row, col| {
+                    self.scalar_field[row][col] > self.threshold
+                        && self.materials[row][col] as usize == material
+                })
+            })
+            .collect();
+
+        for row in row_start..=row_end {
+            for col in col_start..=col_end {
+                let (block_row, block_col) = (row - row_start, col - col_start);
+
+                let index = cell_index(&thresholded_block, block_row, block_col);
+                self.index_grid[row][col] = self.resolve_saddle(row, col, index);
+                for (material, material_block) in material_blocks.iter().enumerate() {
+                    let index = cell_index(material_block, block_row, block_col);
+                    self.material_index_grids[material][row][col] =
+                        self.resolve_saddle(row, col, index);
+                }
+            }
+        }
+    }
+
+    /// Index of the cell at `(row, col)` with the saddle cases decided by `saddle_policy`
+    fn resolve_saddle(&self, row: usize, col: usize, index: u8) -> u8 {
+        if index != 5 && index != 10 {
+            return index;
+        }
+
+        let top_left = self.scalar_field[row][col];
+        let top_right = self.scalar_field[row][col + 1];
+        let bottom_right = self.scalar_field[row + 1][col + 1];
+        let bottom_left = self.scalar_field[row + 1][col];
+
+        let join = match self.saddle_policy {
+            SaddlePolicy::Split => false,
+            SaddlePolicy::Join => true,
+            SaddlePolicy::CenterAverage => {
+                (top_left + top_right + bottom_right + bottom_left) / 4.0 > self.threshold
+            }
+            SaddlePolicy::AsymptoticDecider => {
+                // Value at the saddle point of the bilinear interpolation, the denominator
+                // cannot be zero as the diagonals lie on opposite sides of the threshold
+                let saddle = (top_left * bottom_right - top_right * bottom_left)
+                    / (top_left + bottom_right - top_right - bottom_left);
+                saddle > self.threshold
+            }
+        };
+
+        match (index, join) {
+            (5, true) => JOINED_SADDLE_5,
+            (10, true) => JOINED_SADDLE_10,
+            _ => index,
+        }
+    }
+}
+
+fn point_in_triangle(x: f64, y: f64, triangle: &[f64]) -> bool {
+    let side =
+        |x_0: f64, y_0: f64, x_1: f64, y_1: f64| (x_1 - x_0) * (y - y_0) - (y_1 - y_0) * (x - x_0);
+
+    let d_0 = side(triangle[0], triangle[1], triangle[2], triangle[3]);
+    let d_1 = side(triangle[2], triangle[3], triangle[4], triangle[5]);
+    let d_2 = side(triangle[4], triangle[5], triangle[0], triangle[1]);
+
+    // Inside or on an edge when the point is not on opposite sides of two edges
+    let has_negative = d_0 < 0.0 || d_1 < 0.0 || d_2 < 0.0;
+    let has_positive = d_0 > 0.0 || d_1 > 0.0 || d_2 > 0.0;
+
+    !(has_negative && has_positive)
+}
+
+/// Compose the marching squares case of the cell whose top left sample is `(row, col)`
+pub(crate) fn cell_index(thresholded_field: &IndexField, row: usize, col: usize) -> u8 {
+    // Compose 4 bits at corners of each cell to build a binary index
+    // Start top left and rotate clockwise
+    // Build most significant bit to least significant bit
+    let mut index = 0;
+    index |= thresholded_field[row][col] << 3;
+    index |= thresholded_field[row][col + 1] << 2;
+    index |= thresholded_field[row + 1][col + 1] << 1;
+    index |= thresholded_field[row + 1][col];
+
+    index
 }
 
-fn threshold_field(scalar_field: &ScalarField, threshold: f64) -> IndexField {
-    let mut new_field = Vec::with_capacity(scalar_field.len());
-    for _ in 0..scalar_field.len() {
-        let mut row = Vec::<u8>::with_capacity(scalar_field[0].len());
-        for _ in 0..scalar_field[0].len() {
-            row.push(0);
+pub fn sample_noise(
+    width: usize,
+    height: usize,
+    origin_row: i64,
+    origin_col: i64,
+    modifiers: &[NoiseModifier],
+) -> ScalarField {
+    let noise = TerrainNoise::new(modifiers);
+
+    let mut scalar_field = vec![vec![0.0; width]; height];
+    for (row, samples) in scalar_field.iter_mut().enumerate() {
+        for (col, sample) in samples.iter_mut().enumerate() {
+            *sample = noise.sample(origin_row + row as i64, origin_col + col as i64);
         }
-        new_field.push(row);
     }
 
-    for row in 0..scalar_field.len() {
-        for col in 0..scalar_field[0].len() {
-            if scalar_field[row][col] > threshold {
+    scalar_field
+}
+
+pub fn threshold_field(scalar_field: &ScalarField, threshold: f64) -> IndexField {
+    let mut new_field = vec![vec![0; scalar_field[0].len()]; scalar_field.len()];
+
+    for (row, scalars) in scalar_field.iter().enumerate() {
+        for (col, &scalar) in scalars.iter().enumerate() {
+            if scalar > threshold {
                 new_field[row][col] = 1;
             }
         }
@@ -143,3 +636,259 @@ fn threshold_field(scalar_field: &ScalarField, threshold: f64) -> IndexField {
 
     new_field
 }
+
+/// Samples that are solid and made of `material` as 1 and the rest as 0
+pub fn threshold_material_field(
+    scalar_field: &ScalarField,
+    materials: &MaterialField,
+    threshold: f64,
+    material: u8,
+) -> IndexField {
+    let mut material_field = vec![vec![0; scalar_field[0].len()]; scalar_field.len()];
+
+    for (row, scalars) in scalar_field.iter().enumerate() {
+        for (col, &scalar) in scalars.iter().enumerate() {
+            if scalar > threshold && materials[row][col] == material {
+                material_field[row][col] = 1;
+            }
+        }
+    }
+
+    material_field
+}
+
+pub fn index_field(thresholded_field: &IndexField) -> IndexField {
+    let height = thresholded_field.len();
+    let width = thresholded_field[0].len();
+
+    let mut index_grid = vec![vec![0; width - 1]; height - 1];
+    for (row, indices) in index_grid.iter_mut().enumerate() {
+        for (col, index) in indices.iter_mut().enumerate() {
+            *index = cell_index(thresholded_field, row, col);
+        }
+    }
+
+    index_grid
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::region::{RegionKind, RegionMap};
+
+    fn single_cell(scalar_field: ScalarField, saddle_policy: SaddlePolicy) -> Terrain {
+        let mut terrain = Terrain::from_scalar_field(scalar_field);
+        terrain.saddle_policy = saddle_policy;
+        terrain.construct_index_grid();
+
+        terrain
+    }
+
+    /// Whether the rock of the bilinear interpolation between the corners of a single
+    /// cell connects its top left and bottom right corners, by flood filling a fine grid
+    fn interpolation_joins_diagonal(terrain: &Terrain) -> bool {
+        const STEPS: usize = 400;
+
+        let [top_left, top_right] = [terrain.scalar_field[0][0], terrain.scalar_field[0][1]];
+        let [bottom_left, bottom_right] = [terrain.scalar_field[1][0], terrain.scalar_field[1][1]];
+        let solid = |row: usize, col: usize| {
+            let (x, y) = (col as f64 / STEPS as f64, row as f64 / STEPS as f64);
+            let value = top_left * (1.0 - x) * (1.0 - y)
+                + top_right * x * (1.0 - y)
+                + bottom_left * (1.0 - x) * y
+                + bottom_right * x * y;
+            value > terrain.threshold()
+        };
+
+        let mut visited = vec![vec![false; STEPS + 1]; STEPS + 1];
+        let mut queue = VecDeque::from([(0, 0)]);
+        visited[0][0] = true;
+
+        while let Some((row, col)) = queue.pop_front() {
+            if (row, col) == (STEPS, STEPS) {
+                return true;
+            }
+
+            let neighbours = [
+                (row.wrapping_sub(1), col),
+                (row + 1, col),
+                (row, col.wrapping_sub(1)),
+                (row, col + 1),
+            ];
+            for (next_row, next_col) in neighbours {
+                if next_row <= STEPS
+                    && next_col <= STEPS
+                    && !visited[next_row][next_col]
+                    && solid(next_row, next_col)
+                {
+                    visited[next_row][next_col] = true;
+                    queue.push_back((next_row, next_col));
+                }
+            }
+        }
+
+        false
+    }
+
+    #[test]
+    fn saddle_policies_decide_topology() {
+        // Rock in the top left and bottom right corners, the bottom right barely solid.
+        // The average is above the threshold but the saddle of the interpolation is not.
+        let field = vec![vec![1.0, 0.0], vec![0.0, 0.34]];
+
+        let cases = [
+            (SaddlePolicy::Split, 10),
+            (SaddlePolicy::Join, JOINED_SADDLE_10),
+            (SaddlePolicy::CenterAverage, JOINED_SADDLE_10),
+            (SaddlePolicy::AsymptoticDecider, 10),
+        ];
+        for (policy, index) in cases {
+            let terrain = single_cell(field.clone(), policy);
+            assert_eq!(terrain.index_grid[0][0], index, "{:?}", policy);
+        }
+
+        // Rock in the top right and bottom left corners
+        let terrain = single_cell(
+            vec![vec![0.0, 0.9], vec![0.9, 0.0]],
+            SaddlePolicy::AsymptoticDecider,
+        );
+        assert_eq!(terrain.index_grid[0][0], JOINED_SADDLE_5);
+    }
+
+    #[test]
+    fn material_saddles_follow_the_rock() {
+        let fields = [
+            // Joined by every policy but Split
+            vec![vec![1.0, 0.0], vec![0.0, 0.9]],
+            // Joined by the average but split by the asymptotic decider
+            vec![vec![1.0, 0.0], vec![0.0, 0.34]],
+        ];
+        let policies = [
+            SaddlePolicy::Split,
+            SaddlePolicy::Join,
+            SaddlePolicy::CenterAverage,
+            SaddlePolicy::AsymptoticDecider,
+        ];
+
+        for field in fields {
+            for policy in policies {
+                let mut terrain = single_cell(field.clone(), policy);
+                let material_grid = &terrain.material_index_grids[STONE as usize];
+                assert_eq!(
+                    material_grid[0][0], terrain.index_grid[0][0],
+                    "{:?}",
+                    policy
+                );
+
+                // Rebuilding just the cell decides the same way
+                terrain.material_index_grids[STONE as usize][0][0] = 0;
+                terrain.update_index_grid(0, 0, 0, 0);
+                let material_grid = &terrain.material_index_grids[STONE as usize];
+                assert_eq!(
+                    material_grid[0][0], terrain.index_grid[0][0],
+                    "{:?}",
+                    policy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn asymptotic_decider_matches_interpolated_field() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut checked = (0, 0);
+
+        while checked.0 < 10 || checked.1 < 10 {
+            // Rock in the top left and bottom right corners, cave in the others
+            let terrain = single_cell(
+                vec![
+                    vec![rng.gen_range(0.34..1.0), rng.gen_range(0.0..0.33)],
+                    vec![rng.gen_range(0.0..0.33), rng.gen_range(0.34..1.0)],
+                ],
+                SaddlePolicy::AsymptoticDecider,
+            );
+
+            let [[a, b], [c, d]] = [
+                [terrain.scalar_field[0][0], terrain.scalar_field[0][1]],
+                [terrain.scalar_field[1][0], terrain.scalar_field[1][1]],
+            ];
+            let saddle = (a * d - b * c) / (a + d - b - c);
+            // Too close to call on the fine grid
+            if (saddle - terrain.threshold()).abs() < 0.02 {
+                continue;
+            }
+
+            let joined = interpolation_joins_diagonal(&terrain);
+            if joined {
+                checked.0 += 1;
+            } else {
+                checked.1 += 1;
+            }
+
+            // The drawn rock covers the middle of the cell exactly when the field joins
+            // the corners, and the regions agree with the drawing
+            assert_eq!(terrain.is_solid(1.0, 1.0), joined);
+
+            let region_map = RegionMap::new(&terrain);
+            let solid_regions = region_map.regions_of_kind(RegionKind::Solid).count();
+            let empty_regions = region_map.regions_of_kind(RegionKind::Empty).count();
+            assert_eq!(solid_regions, if joined { 1 } else { 2 });
+            assert_eq!(empty_regions, if joined { 2 } else { 1 });
+        }
+    }
+
+    /// 12x12 cave holding a wall of rock along the right edge with a one sample pocket
+    /// in it, a block of fifteen samples, an island of six and an island of two
+    fn islands_and_pockets() -> Terrain {
+        let mut scalar_field = vec![vec![0.0; 12]; 12];
+        for (row, scalars) in scalar_field.iter_mut().enumerate() {
+            scalars[9..].fill(1.0);
+            if (2..=6).contains(&row) {
+                scalars[5..=7].fill(1.0);
+            }
+            if (8..=9).contains(&row) {
+                scalars[2..=4].fill(1.0);
+            }
+        }
+        scalar_field[4][10] = 0.0;
+        scalar_field[2][2] = 1.0;
+        scalar_field[2][3] = 1.0;
+
+        Terrain::from_scalar_field(scalar_field)
+    }
+
+    #[test]
+    fn small_regions_are_removed() {
+        let mut terrain = islands_and_pockets();
+
+        assert_eq!(terrain.remove_small_regions(2, 5), (1, 1));
+
+        // The pocket is filled and the small island worn away, the rest stays
+        assert!(terrain.scalar_field[4][10] > terrain.threshold());
+        assert!(terrain.scalar_field[2][2] <= terrain.threshold());
+        assert!(terrain.scalar_field[2][3] <= terrain.threshold());
+        assert!(terrain.scalar_field[8][2] > terrain.threshold());
+        assert!(terrain.scalar_field[2][5] > terrain.threshold());
+
+        let region_map = RegionMap::new(&terrain);
+        assert_eq!(region_map.regions_of_kind(RegionKind::Empty).count(), 1);
+        assert_eq!(region_map.regions_of_kind(RegionKind::Solid).count(), 3);
+    }
+
+    #[test]
+    fn largest_regions_are_kept() {
+        let mut terrain = islands_and_pockets();
+
+        // Limits larger than any region still leave the largest cave and rock
+        assert_eq!(terrain.remove_small_regions(1000, 1000), (1, 3));
+
+        let region_map = RegionMap::new(&terrain);
+        assert_eq!(region_map.regions.len(), 2);
+        assert!(terrain.scalar_field[4][10] > terrain.threshold());
+        assert!(terrain.scalar_field[0][0] <= terrain.threshold());
+    }
+}
@@ -0,0 +1,473 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, TAU};
+
+use log::debug;
+use noise::{NoiseFn, Perlin};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    region::{RegionKind, RegionMap},
+    terrain::Terrain,
+};
+
+/// Largest distance in samples between consecutive points carved along a tunnel
+const STEP: f64 = 0.25;
+
+/// The nearest sample to any point on a path is at most this far away, a narrower
+/// tunnel could leave gaps between the carved samples
+const MIN_RADIUS: f64 = std::f64::consts::FRAC_1_SQRT_2 + 0.05;
+
+/// Pair of sample positions `(row, col)` joining two regions
+type Link = ((usize, usize), (usize, usize));
+
+/// Fewest steps left for a walker to split off a branch
+const MIN_BRANCH_STEPS: usize = 4;
+
+/// How a walker picks its heading
+#[derive(Clone, Copy, Debug)]
+pub enum WalkStyle {
+    /// Turn by a random angle every step, giving jagged, cramped tunnels
+    Drunkard,
+    /// Turn by an amount following Perlin noise along the walk, giving long smooth
+    /// bends. `noise_scale` is the noise frequency per step.
+    Worm { noise_scale: f64 },
+}
+
+/// Walkers carving tunnels through the scalar field, one sample per step
+#[derive(Clone, Copy, Debug)]
+pub struct TunnelWalk {
+    pub style: WalkStyle,
+    /// Number of walkers, each starting at a random sample
+    pub count: usize,
+    pub steps: usize,
+    /// Largest turn per step in radians
+    pub turn_rate: f64,
+    /// Tunnel radius in samples
+    pub radius: f64,
+    /// How much the radius swells and narrows along the tunnel, as a fraction of it
+    pub radius_variation: f64,
+    /// Chance per step of splitting off a branch, which walks for half the steps left.
+    /// Must be between 0 and 1.
+    pub branch_chance: f64,
+}
+
+/// Long branching worms with a few drunkard's walks for side chambers
+pub fn winding_tunnels() -> Vec<TunnelWalk> {
+    vec![
+        TunnelWalk {
+            style: WalkStyle::Worm { noise_scale: 0.1 },
+            count: 2,
+            steps: 60,
+            turn_rate: 0.5,
+            radius: 1.2,
+            radius_variation: 0.4,
+            branch_chance: 0.03,
+        },
+        TunnelWalk {
+            style: WalkStyle::Drunkard,
+            count: 2,
+            steps: 25,
+            turn_rate: 1.2,
+            radius: 1.0,
+            radius_variation: 0.3,
+            branch_chance: 0.0,
+        },
+    ]
+}
+
+/// A walker in progress, branches are queued as new walkers
+struct Walker {
+    row: f64,
+    col: f64,
+    heading: f64,
+    steps: usize,
+}
+
+impl Terrain {
+    /// Carve tunnels until every empty region of the cave is connected.
+    ///
+    /// Regions are joined along a minimum spanning tree of the shortest distances between
+    /// them. `tunnel_width` is in samples, `wobble` is how far in samples a tunnel may bend
+    /// away from the straight line, and `seed` picks the bends.
+    ///
+    /// Returns the number of tunnels carved.
+    pub fn connect_regions(&mut self, tunnel_width: f64, wobble: f64, seed: u32) -> usize {
+        let region_map = RegionMap::new(self);
+
+        let regions: Vec<Vec<(usize, usize)>> = region_map
+            .regions_of_kind(RegionKind::Empty)
+            .map(|(label, _)| boundary_samples(&region_map, label))
+            .collect();
+        debug!("connecting {} empty regions", regions.len());
+
+        let links = minimum_spanning_tree(&regions);
+
+        let perlin = Perlin::new(seed);
+        for (index, &(from, to)) in links.iter().enumerate() {
+            debug!("carving tunnel from {:?} to {:?}", from, to);
+            self.carve_tunnel(from, to, tunnel_width, wobble, &perlin, index);
+        }
+
+        self.construct_index_grid();
+
+        links.len()
+    }
+
+    /// Carve winding tunnels by walking through the terrain.
+    ///
+    /// Where the walkers start, turn and branch is picked by `seed`. Samples are only ever
+    /// lowered, so the walks can be layered on top of any base, noise or otherwise. Walkers
+    /// bounce off the edges of the terrain.
+    ///
+    /// Returns the number of tunnels carved, branches included.
+    pub fn carve_walks(&mut self, walks: &[TunnelWalk], seed: u64) -> usize {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut tunnels = 0;
+
+        for walk in walks {
+            assert!(
+                (0.0..=1.0).contains(&walk.branch_chance),
+                "branch_chance must be between 0 and 1, got {}",
+                walk.branch_chance
+            );
+        }
+
+        for walk in walks {
+            debug!("carving {} {:?} walks", walk.count, walk.style);
+
+            let turn_noise = Perlin::new(rng.gen());
+            let radius_noise = Perlin::new(rng.gen());
+
+            let mut walkers: Vec<Walker> = (0..walk.count)
+                .map(|_| Walker {
+                    row: rng.gen_range(0.0..self.height as f64),
+                    col: rng.gen_range(0.0..self.width as f64),
+                    heading: rng.gen_range(0.0..TAU),
+                    steps: walk.steps,
+                })
+                .collect();
+
+            while let Some(mut walker) = walkers.pop() {
+                // Every tunnel samples its own stretch of the noise
+                let noise_offset = tunnels as f64 * 10.0 + 0.5;
+                tunnels += 1;
+
+                for step in 0..walker.steps {
+                    let radius = walk.radius
+                        * (1.0
+                            + walk.radius_variation
+                                * radius_noise.get([step as f64 * 0.1, noise_offset]));
+                    let (row, col) = (walker.row, walker.col);
+
+                    walker.heading += match walk.style {
+                        WalkStyle::Drunkard => rng.gen_range(-walk.turn_rate..=walk.turn_rate),
+                        WalkStyle::Worm { noise_scale } => {
+                            turn_noise.get([step as f64 * noise_scale, noise_offset])
+                                * walk.turn_rate
+                        }
+                    };
+                    self.walk_step(&mut walker);
+                    self.carve_segment((row, col), (walker.row, walker.col), radius);
+
+                    let steps_left = walker.steps - step - 1;
+                    if steps_left >= MIN_BRANCH_STEPS && rng.gen_bool(walk.branch_chance) {
+                        let side = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+                        walkers.push(Walker {
+                            heading: walker.heading + side * rng.gen_range(FRAC_PI_4..FRAC_PI_2),
+                            steps: steps_left / 2,
+                            ..walker
+                        });
+                    }
+                }
+            }
+        }
+
+        self.construct_index_grid();
+
+        tunnels
+    }
+
+    /// Move a walker one sample along its heading, reflecting it off the edges of the terrain
+    fn walk_step(&self, walker: &mut Walker) {
+        let max_row = (self.height - 1) as f64;
+        let max_col = (self.width - 1) as f64;
+
+        let mut row = walker.row + walker.heading.sin();
+        let mut col = walker.col + walker.heading.cos();
+
+        if !(0.0..=max_row).contains(&row) {
+            row = row.clamp(0.0, max_row);
+            walker.heading = -walker.heading;
+        }
+        if !(0.0..=max_col).contains(&col) {
+            col = col.clamp(0.0, max_col);
+            walker.heading = std::f64::consts::PI - walker.heading;
+        }
+
+        walker.row = row;
+        walker.col = col;
+    }
+
+    /// Carve discs close enough together along a straight line to leave no gaps
+    fn carve_segment(&mut self, from: (f64, f64), to: (f64, f64), radius: f64) {
+        let radius = radius.max(MIN_RADIUS);
+        let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+        let steps = (length / STEP).ceil().max(1.0) as usize;
+
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            self.carve_disc(
+                from.0 + (to.0 - from.0) * t,
+                from.1 + (to.1 - from.1) * t,
+                radius,
+            );
+        }
+    }
+
+    fn carve_tunnel(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        tunnel_width: f64,
+        wobble: f64,
+        perlin: &Perlin,
+        index: usize,
+    ) {
+        let radius = (tunnel_width / 2.0).max(MIN_RADIUS);
+
+        let (from_row, from_col) = (from.0 as f64, from.1 as f64);
+        let (row_delta, col_delta) = (to.0 as f64 - from_row, to.1 as f64 - from_col);
+        let length = (row_delta * row_delta + col_delta * col_delta).sqrt();
+
+        // Perpendicular to the tunnel, the direction the wobble pushes the path
+        let (normal_row, normal_col) = if length > 0.0 {
+            (-col_delta / length, row_delta / length)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let max_row = (self.height - 1) as f64;
+        let max_col = (self.width - 1) as f64;
+        let path_point = |t: f64| {
+            // Fade the wobble out towards both ends so the tunnel still meets its endpoints
+            let bend = perlin.get([t * length * 0.15, index as f64 * 10.0 + 0.5])
+                * wobble
+                * (t * std::f64::consts::PI).sin();
+
+            // Keep the path on the terrain, a point bent off the edge would leave a gap
+            (
+                (from_row + row_delta * t + normal_row * bend).clamp(0.0, max_row),
+                (from_col + col_delta * t + normal_col * bend).clamp(0.0, max_col),
+            )
+        };
+
+        let mut t = 0.0;
+        let mut t_step = 1.0 / (length / STEP).ceil().max(1.0);
+        let mut point = path_point(t);
+        self.carve_disc(point.0, point.1, radius);
+
+        while t < 1.0 {
+            let next_t = (t + t_step).min(1.0);
+            let next_point = path_point(next_t);
+
+            // Bends move the path faster than the straight line does, shorten the step
+            // until consecutive points are close enough to overlap
+            let distance =
+                ((next_point.0 - point.0).powi(2) + (next_point.1 - point.1).powi(2)).sqrt();
+            if distance > STEP && t_step > f64::EPSILON {
+                t_step /= 2.0;
+                continue;
+            }
+
+            t = next_t;
+            point = next_point;
+            self.carve_disc(point.0, point.1, radius);
+
+            if distance < STEP / 2.0 {
+                t_step *= 2.0;
+            }
+        }
+    }
+
+    /// Lower the samples within `radius` of `(row, col)` below the threshold, deepest at the center
+    fn carve_disc(&mut self, row: f64, col: f64, radius: f64) {
+        let row_start = (row - radius).floor().max(0.0) as usize;
+        let col_start = (col - radius).floor().max(0.0) as usize;
+        let row_end = ((row + radius).ceil().max(0.0) as usize).min(self.height - 1);
+        let col_end = ((col + radius).ceil().max(0.0) as usize).min(self.width - 1);
+
+        for sample_row in row_start..=row_end {
+            for sample_col in col_start..=col_end {
+                let row_distance = sample_row as f64 - row;
+                let col_distance = sample_col as f64 - col;
+                let distance = (row_distance * row_distance + col_distance * col_distance).sqrt();
+
+                if distance >= radius {
+                    continue;
+                }
+
+                let carved = self.threshold() * distance / radius;
+                let scalar = &mut self.scalar_field[sample_row][sample_col];
+                *scalar = scalar.min(carved);
+            }
+        }
+    }
+}
+
+/// Samples of a region that touch a sample of another region, the only candidates
+/// for the closest point to another region
+fn boundary_samples(region_map: &RegionMap, label: usize) -> Vec<(usize, usize)> {
+    let (min_row, min_col, max_row, max_col) = region_map.regions[label].bounding_box;
+    let height = region_map.labels.len();
+    let width = region_map.labels[0].len();
+
+    let mut samples = Vec::new();
+    for row in min_row..=max_row {
+        for col in min_col..=max_col {
+            if region_map.labels[row][col] != label {
+                continue;
+            }
+
+            let on_boundary = (row > 0 && region_map.labels[row - 1][col] != label)
+                || (row + 1 < height && region_map.labels[row + 1][col] != label)
+                || (col > 0 && region_map.labels[row][col - 1] != label)
+                || (col + 1 < width && region_map.labels[row][col + 1] != label);
+
+            if on_boundary {
+                samples.push((row, col));
+            }
+        }
+    }
+
+    samples
+}
+
+/// Closest pair of samples between two regions and their squared distance
+fn closest_link(a: &[(usize, usize)], b: &[(usize, usize)]) -> (usize, Link) {
+    let mut best = (usize::MAX, (a[0], b[0]));
+
+    for &from in a {
+        for &to in b {
+            let row_distance = from.0.abs_diff(to.0);
+            let col_distance = from.1.abs_diff(to.1);
+            let distance = row_distance * row_distance + col_distance * col_distance;
+
+            if distance < best.0 {
+                best = (distance, (from, to));
+            }
+        }
+    }
+
+    best
+}
+
+/// Prim's algorithm over the complete graph of regions
+fn minimum_spanning_tree(regions: &[Vec<(usize, usize)>]) -> Vec<Link> {
+    if regions.is_empty() {
+        return Vec::new();
+    }
+
+    let mut in_tree = vec![false; regions.len()];
+    in_tree[0] = true;
+
+    // Cheapest known link from the tree to every region outside of it
+    let mut cheapest: Vec<(usize, Link)> = regions
+        .iter()
+        .map(|region| closest_link(&regions[0], region))
+        .collect();
+
+    let mut links = Vec::with_capacity(regions.len() - 1);
+    for _ in 1..regions.len() {
+        let (next, &(_, link)) = cheapest
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| !in_tree[index])
+            .min_by_key(|&(_, &(distance, _))| distance)
+            .unwrap();
+
+        in_tree[next] = true;
+        links.push(link);
+
+        for (index, region) in regions.iter().enumerate() {
+            if in_tree[index] {
+                continue;
+            }
+
+            let candidate = closest_link(&regions[next], region);
+            if candidate.0 < cheapest[index].0 {
+                cheapest[index] = candidate;
+            }
+        }
+    }
+
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_region_count(terrain: &Terrain) -> usize {
+        RegionMap::new(terrain)
+            .regions_of_kind(RegionKind::Empty)
+            .count()
+    }
+
+    #[test]
+    fn carve_walks_is_deterministic() {
+        let mut a = Terrain::new(40, 40);
+        let mut b = Terrain::new(40, 40);
+
+        let tunnels = a.carve_walks(&winding_tunnels(), 3);
+
+        assert_eq!(b.carve_walks(&winding_tunnels(), 3), tunnels);
+        assert_eq!(a.scalar_field, b.scalar_field);
+    }
+
+    #[test]
+    fn carve_walks_only_lowers_samples() {
+        let base = Terrain::new(40, 40);
+        let mut terrain = Terrain::new(40, 40);
+
+        terrain.carve_walks(&winding_tunnels(), 5);
+
+        let mut lowered = 0;
+        for (row, scalars) in terrain.scalar_field.iter().enumerate() {
+            for (col, &scalar) in scalars.iter().enumerate() {
+                assert!(scalar <= base.scalar_field[row][col]);
+                if scalar < base.scalar_field[row][col] {
+                    lowered += 1;
+                }
+            }
+        }
+        assert!(lowered > 0);
+    }
+
+    #[test]
+    fn connect_regions_leaves_one_empty_region() {
+        let mut terrain = Terrain::new(40, 40);
+        assert!(empty_region_count(&terrain) > 1);
+
+        terrain.connect_regions(2.0, 3.0, 7);
+
+        assert_eq!(empty_region_count(&terrain), 1);
+    }
+
+    #[test]
+    fn connect_regions_with_narrow_tunnels_on_non_square_terrain() {
+        let mut terrain = Terrain::with_origin(120, 50, -300, 80);
+        assert!(empty_region_count(&terrain) > 1);
+
+        terrain.connect_regions(0.5, 6.0, 1);
+
+        assert_eq!(empty_region_count(&terrain), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "branch_chance")]
+    fn branch_chance_below_zero_is_rejected() {
+        let mut walks = winding_tunnels();
+        walks[1].branch_chance = -0.1;
+
+        Terrain::new(40, 40).carve_walks(&walks, 0);
+    }
+}
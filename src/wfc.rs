@@ -0,0 +1,422 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::terrain::{index_field, Terrain};
+
+/// Marching squares cases, saddles are resolved again once the terrain is built
+const TILES: usize = 16;
+
+/// Contradictions undone before giving up on a seed
+const MAX_BACKTRACKS: usize = 10_000;
+
+/// Neighbour offsets as `(row, col)`, in the order of `TileRules::compatible`
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+/// Which marching squares cases may sit next to each other, learned from an example
+#[derive(Clone, Debug)]
+pub struct TileRules {
+    /// For every direction in `DIRECTIONS` and every case, the cases seen next to it
+    /// as a bit set
+    compatible: [[u16; TILES]; 4],
+    /// How often every case appears in the example
+    weights: [f64; TILES],
+}
+
+impl TileRules {
+    /// Learn the cases and their neighbours from the index grid of a hand-painted terrain.
+    ///
+    /// Only pairs seen in the example are allowed, which also keeps the corners shared
+    /// by neighbouring cells consistent.
+    pub fn learn(example: &Terrain) -> Self {
+        let index_grid = index_field(&example.thresholded_field());
+        let mut compatible = [[0; TILES]; 4];
+        let mut weights = [0.0; TILES];
+
+        let rows = index_grid.len() as isize;
+        let cols = index_grid[0].len() as isize;
+        for row in 0..rows {
+            for col in 0..cols {
+                let tile = index_grid[row as usize][col as usize] as usize;
+                weights[tile] += 1.0;
+
+                for (direction, &(row_step, col_step)) in DIRECTIONS.iter().enumerate() {
+                    let (next_row, next_col) = (row + row_step, col + col_step);
+                    if (0..rows).contains(&next_row) && (0..cols).contains(&next_col) {
+                        let next = index_grid[next_row as usize][next_col as usize];
+                        compatible[direction][tile] |= 1 << next;
+                    }
+                }
+            }
+        }
+        debug!("learned tile weights {:?}", weights);
+
+        Self {
+            compatible,
+            weights,
+        }
+    }
+
+    /// Build a new terrain of `width` x `height` samples whose marching squares cases
+    /// only neighbour each other the way they do in the example.
+    ///
+    /// Cells are collapsed lowest entropy first, picking a case by how often it appears
+    /// in the example. When a choice leaves some cell without any case the choice is
+    /// undone and ruled out. Returns `None` when there are too many contradictions to
+    /// resolve, another seed may still succeed, or when the terrain is too small to
+    /// hold a single cell.
+    pub fn synthesize(&self, width: usize, height: usize, seed: u64) -> Option<Terrain> {
+        if width < 2 || height < 2 {
+            return None;
+        }
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut wave = Wave::new(self, width - 1, height - 1);
+        let backtracks = self.collapse(&mut wave, &mut rng)?;
+        debug!("synthesized terrain after {} backtracks", backtracks);
+
+        // Corners shared between cells agree, so every cell can write its own corners
+        let mut scalar_field = vec![vec![0.0; width]; height];
+        for row in 0..height - 1 {
+            for col in 0..width - 1 {
+                let tile = wave.cells[row * (width - 1) + col].trailing_zeros();
+                let corners = [(0, 0, 3), (0, 1, 2), (1, 1, 1), (1, 0, 0)];
+                for (row_offset, col_offset, bit) in corners {
+                    if tile >> bit & 1 == 1 {
+                        scalar_field[row + row_offset][col + col_offset] = 1.0;
+                    }
+                }
+            }
+        }
+
+        Some(Terrain::from_scalar_field(scalar_field))
+    }
+
+    /// Collapse every cell of the wave to a single case, returns how many choices had
+    /// to be undone
+    fn collapse(&self, wave: &mut Wave, rng: &mut StdRng) -> Option<usize> {
+        let entropies = self.entropies();
+        let mut heap = wave.entropy_heap(&entropies, rng);
+
+        // Trail length, cell and case of every choice still standing
+        let mut choices: Vec<(usize, usize, usize)> = Vec::new();
+        let mut backtracks = 0;
+
+        loop {
+            let cell = loop {
+                match heap.pop() {
+                    Some(Reverse((key, cell))) => {
+                        // Entries go stale as the options of their cell shrink
+                        let options = wave.cells[cell];
+                        if options.count_ones() > 1 && key >> 16 == entropies[options as usize] {
+                            break Some(cell);
+                        }
+                    }
+                    None => break None,
+                }
+            };
+            let Some(cell) = cell else {
+                break;
+            };
+
+            let tile = self.pick_tile(wave.cells[cell], rng);
+            choices.push((wave.trail.len(), cell, tile));
+
+            let mut consistent = wave.restrict(self, cell, 1 << tile, &entropies, &mut heap, rng);
+            let backtracked = !consistent;
+            while !consistent {
+                backtracks += 1;
+                if backtracks > MAX_BACKTRACKS {
+                    debug!("giving up after {} backtracks", MAX_BACKTRACKS);
+                    return None;
+                }
+
+                // Every case has been ruled out somewhere, the example cannot be continued
+                let (trail_len, cell, tile) = choices.pop()?;
+                wave.undo(trail_len);
+
+                let options = wave.cells[cell] & !(1 << tile);
+                consistent =
+                    options != 0 && wave.restrict(self, cell, options, &entropies, &mut heap, rng);
+            }
+
+            // Undone cells got their options back but lost their place in the heap
+            if backtracked {
+                heap = wave.entropy_heap(&entropies, rng);
+            }
+        }
+
+        Some(backtracks)
+    }
+
+    /// Shannon entropy of every set of cases, weighted by how often they appear
+    fn entropies(&self) -> Vec<u64> {
+        (0..=u16::MAX)
+            .map(|options| {
+                let (mut sum, mut weighted_log) = (0.0, 0.0);
+                for tile in 0..TILES {
+                    let weight = self.weights[tile];
+                    if options >> tile & 1 == 1 && weight > 0.0 {
+                        sum += weight;
+                        weighted_log += weight * f64::ln(weight);
+                    }
+                }
+
+                let entropy = if sum > 0.0 {
+                    f64::ln(sum) - weighted_log / sum
+                } else {
+                    0.0
+                };
+
+                // Fixed point so the heap can order them, with 16 bits left free for noise
+                (entropy.max(0.0) * (1u64 << 32) as f64) as u64
+            })
+            .collect()
+    }
+
+    fn pick_tile(&self, options: u16, rng: &mut StdRng) -> usize {
+        let total: f64 = (0..TILES)
+            .filter(|&tile| options >> tile & 1 == 1)
+            .map(|tile| self.weights[tile])
+            .sum();
+
+        let mut target = rng.gen_range(0.0..total);
+        for tile in 0..TILES {
+            if options >> tile & 1 == 1 {
+                target -= self.weights[tile];
+                if target < 0.0 {
+                    return tile;
+                }
+            }
+        }
+
+        (0..TILES)
+            .rev()
+            .find(|&tile| options >> tile & 1 == 1)
+            .unwrap()
+    }
+}
+
+/// Cases still possible for every cell, with a trail of changes for backtracking
+struct Wave {
+    cells: Vec<u16>,
+    cols: usize,
+    rows: usize,
+    /// Cell and its options before every change, in order
+    trail: Vec<(usize, u16)>,
+}
+
+type EntropyHeap = BinaryHeap<Reverse<(u64, usize)>>;
+
+impl Wave {
+    fn new(rules: &TileRules, cols: usize, rows: usize) -> Self {
+        let seen = (0..TILES)
+            .filter(|&tile| rules.weights[tile] > 0.0)
+            .fold(0, |options, tile| options | 1 << tile);
+
+        Self {
+            cells: vec![seen; cols * rows],
+            cols,
+            rows,
+            trail: Vec::new(),
+        }
+    }
+
+    /// Every undecided cell keyed by its entropy, with a little noise to break ties
+    fn entropy_heap(&self, entropies: &[u64], rng: &mut StdRng) -> EntropyHeap {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, options)| options.count_ones() > 1)
+            .map(|(cell, &options)| Reverse((heap_key(entropies, options, rng), cell)))
+            .collect()
+    }
+
+    /// Narrow a cell down to `options` and propagate to its neighbours, returns false
+    /// when some cell is left without any case
+    fn restrict(
+        &mut self,
+        rules: &TileRules,
+        cell: usize,
+        options: u16,
+        entropies: &[u64],
+        heap: &mut EntropyHeap,
+        rng: &mut StdRng,
+    ) -> bool {
+        self.set(cell, options);
+        let mut stack = vec![cell];
+
+        while let Some(cell) = stack.pop() {
+            let (row, col) = ((cell / self.cols) as isize, (cell % self.cols) as isize);
+
+            for (direction, &(row_step, col_step)) in DIRECTIONS.iter().enumerate() {
+                let (next_row, next_col) = (row + row_step, col + col_step);
+                if !(0..self.rows as isize).contains(&next_row)
+                    || !(0..self.cols as isize).contains(&next_col)
+                {
+                    continue;
+                }
+                let next = next_row as usize * self.cols + next_col as usize;
+
+                let allowed = (0..TILES)
+                    .filter(|&tile| self.cells[cell] >> tile & 1 == 1)
+                    .fold(0, |allowed, tile| {
+                        allowed | rules.compatible[direction][tile]
+                    });
+                let options = self.cells[next] & allowed;
+
+                if options == 0 {
+                    return false;
+                }
+                if options != self.cells[next] {
+                    self.set(next, options);
+                    stack.push(next);
+                    if options.count_ones() > 1 {
+                        heap.push(Reverse((heap_key(entropies, options, rng), next)));
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    fn set(&mut self, cell: usize, options: u16) {
+        self.trail.push((cell, self.cells[cell]));
+        self.cells[cell] = options;
+    }
+
+    /// Roll back every change made after the trail was `trail_len` long
+    fn undo(&mut self, trail_len: usize) {
+        while self.trail.len() > trail_len {
+            let (cell, options) = self.trail.pop().unwrap();
+            self.cells[cell] = options;
+        }
+    }
+}
+
+fn heap_key(entropies: &[u64], options: u16, rng: &mut StdRng) -> u64 {
+    entropies[options as usize] << 16 | rng.gen::<u16>() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every pair of neighbouring cases in `terrain` is allowed by `rules`
+    fn follows_rules(terrain: &Terrain, rules: &TileRules) -> bool {
+        let learned = TileRules::learn(terrain);
+
+        (0..4).all(|direction| {
+            (0..TILES).all(|tile| {
+                learned.compatible[direction][tile] & !rules.compatible[direction][tile] == 0
+            })
+        })
+    }
+
+    #[test]
+    fn synthesized_cases_follow_the_example() {
+        let rules = TileRules::learn(&Terrain::new(30, 30));
+
+        for seed in 0..4 {
+            let terrain = rules.synthesize(45, 25, seed).unwrap();
+
+            assert_eq!((terrain.width, terrain.height), (45, 25));
+            assert!(follows_rules(&terrain, &rules));
+        }
+    }
+
+    #[test]
+    fn synthesize_is_deterministic() {
+        let rules = TileRules::learn(&Terrain::new(30, 30));
+
+        let a = rules.synthesize(40, 40, 6).unwrap();
+        let b = rules.synthesize(40, 40, 6).unwrap();
+
+        assert_eq!(a.scalar_field, b.scalar_field);
+    }
+
+    #[test]
+    fn synthesize_from_sparse_example() {
+        // Square blocks of rock four samples wide, spaced apart by open cave
+        let mut example = Terrain::new(20, 20);
+        for row in 0..example.height {
+            for col in 0..example.width {
+                let solid = (2..6).contains(&(row % 8)) && (2..6).contains(&(col % 8));
+                example.scalar_field[row][col] = if solid { 1.0 } else { 0.0 };
+            }
+        }
+        example.construct_index_grid();
+        let rules = TileRules::learn(&example);
+
+        let terrain = rules.synthesize(30, 30, 2).unwrap();
+
+        assert!(follows_rules(&terrain, &rules));
+    }
+
+    #[test]
+    fn undo_restores_options() {
+        let rules = TileRules::learn(&Terrain::new(30, 30));
+        let entropies = rules.entropies();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mut wave = Wave::new(&rules, 10, 10);
+        let before = wave.cells.clone();
+        let mut heap = wave.entropy_heap(&entropies, &mut rng);
+
+        assert!(wave.restrict(&rules, 55, 1 << 15, &entropies, &mut heap, &mut rng));
+        assert_ne!(wave.cells, before);
+
+        wave.undo(0);
+        assert_eq!(wave.cells, before);
+    }
+
+    #[test]
+    fn collapse_backtracks_out_of_contradictions() {
+        // Three cases that may not sit next to themselves, which propagation alone
+        // cannot see through, so some choices end in a cell with no case left
+        let tiles = [0, 5, 10];
+        let mut compatible = [[0; TILES]; 4];
+        let mut weights = [0.0; TILES];
+        for tile in tiles {
+            weights[tile] = 1.0;
+            for neighbours in &mut compatible {
+                neighbours[tile] = tiles
+                    .iter()
+                    .filter(|&&other| other != tile)
+                    .fold(0, |options, other| options | 1 << other);
+            }
+        }
+        let rules = TileRules {
+            compatible,
+            weights,
+        };
+
+        let mut wave = Wave::new(&rules, 20, 20);
+        let mut rng = StdRng::seed_from_u64(0);
+        let backtracks = rules.collapse(&mut wave, &mut rng).unwrap();
+        assert!(backtracks > 0);
+
+        for row in 0..wave.rows {
+            for col in 0..wave.cols {
+                let options = wave.cells[row * wave.cols + col];
+                assert_eq!(options.count_ones(), 1);
+                if col + 1 < wave.cols {
+                    assert_ne!(options, wave.cells[row * wave.cols + col + 1]);
+                }
+                if row + 1 < wave.rows {
+                    assert_ne!(options, wave.cells[(row + 1) * wave.cols + col]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn synthesize_rejects_terrain_without_cells() {
+        let rules = TileRules::learn(&Terrain::new(30, 30));
+
+        assert!(rules.synthesize(1, 10, 0).is_none());
+        assert!(rules.synthesize(10, 0, 0).is_none());
+    }
+}
@@ -1,9 +1,10 @@
 use pixels::{Pixels, SurfaceTexture};
-use winit::dpi::PhysicalPosition;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::{dpi::LogicalSize, event_loop::EventLoop, window::WindowBuilder};
 
 pub struct Window {
     window: winit::window::Window,
+    pixel_size: usize,
     pub screen: Screen,
 }
 
@@ -19,18 +20,38 @@ impl Window {
                 (screen_width * pixel_size) as f64,
                 (screen_height * pixel_size) as f64,
             ))
-            .with_resizable(false)
+            .with_resizable(true)
             .build(&event_loop)
             .unwrap();
 
         let screen = Screen::new(screen_width, screen_height, &window);
 
-        (Self { window, screen }, event_loop)
+        (
+            Self {
+                window,
+                pixel_size,
+                screen,
+            },
+            event_loop,
+        )
     }
 
     pub fn request_redraw(&self) {
         self.window.request_redraw()
     }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        // A minimized window reports a zero size, keep the old buffer until it comes back
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        let screen_width = (size.width as usize / self.pixel_size).max(1);
+        let screen_height = (size.height as usize / self.pixel_size).max(1);
+
+        self.screen
+            .resize(screen_width, screen_height, size.width, size.height);
+    }
 }
 
 pub struct Screen {
@@ -38,7 +59,15 @@ pub struct Screen {
     height: usize,
     aspect_ratio: f64,
 
-    pixels: Pixels,
+    frame: Frame,
+}
+
+/// Where the pixels of a screen live
+enum Frame {
+    /// Presented to a window through the GPU
+    Window(Box<Pixels>),
+    /// Kept in memory only, for benchmarks and offscreen rendering
+    Headless(Vec<u8>),
 }
 
 impl Screen {
@@ -60,7 +89,18 @@ impl Screen {
             width,
             height,
             aspect_ratio,
-            pixels,
+            frame: Frame::Window(Box::new(pixels)),
+        }
+    }
+
+    pub fn headless(width: usize, height: usize) -> Self {
+        let aspect_ratio = width as f64 / height as f64;
+
+        Self {
+            width,
+            height,
+            aspect_ratio,
+            frame: Frame::Headless(vec![0; width * height * 4]),
         }
     }
 
@@ -72,17 +112,56 @@ impl Screen {
         self.height
     }
 
+    pub fn aspect_ratio(&self) -> f64 {
+        self.aspect_ratio
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize, surface_width: u32, surface_height: u32) {
+        match &mut self.frame {
+            Frame::Window(pixels) => {
+                pixels
+                    .resize_surface(surface_width, surface_height)
+                    .unwrap();
+                pixels.resize_buffer(width as u32, height as u32).unwrap();
+            }
+            Frame::Headless(buffer) => buffer.resize(width * height * 4, 0),
+        }
+
+        self.width = width;
+        self.height = height;
+        self.aspect_ratio = width as f64 / height as f64;
+    }
+
+    pub fn frame(&self) -> &[u8] {
+        match &self.frame {
+            Frame::Window(pixels) => pixels.frame(),
+            Frame::Headless(buffer) => buffer,
+        }
+    }
+
     pub fn frame_mut(&mut self) -> &mut [u8] {
-        self.pixels.frame_mut()
+        match &mut self.frame {
+            Frame::Window(pixels) => pixels.frame_mut(),
+            Frame::Headless(buffer) => buffer,
+        }
     }
 
     pub fn draw_frame(&self) -> Result<(), pixels::Error> {
-        self.pixels.render()
+        match &self.frame {
+            Frame::Window(pixels) => pixels.render(),
+            Frame::Headless(_) => Ok(()),
+        }
     }
 
     pub fn window_pos_to_pixel(&self, pos: PhysicalPosition<f64>) -> (usize, usize) {
-        self.pixels
-            .window_pos_to_pixel(pos.into())
-            .unwrap_or_else(|pos| self.pixels.clamp_pixel_pos(pos))
+        match &self.frame {
+            Frame::Window(pixels) => pixels
+                .window_pos_to_pixel(pos.into())
+                .unwrap_or_else(|pos| pixels.clamp_pixel_pos(pos)),
+            Frame::Headless(_) => (
+                (pos.x.max(0.0) as usize).min(self.width - 1),
+                (pos.y.max(0.0) as usize).min(self.height - 1),
+            ),
+        }
     }
 }